@@ -0,0 +1,103 @@
+use bytes::{Bytes, IntoBuf};
+use obelisk::net::capture::{self, Direction, Record, State};
+use obelisk::net::packets::handshake::serverbound::Handshake;
+use obelisk::net::packets::Field;
+use obelisk::net::version::Version;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::SystemTime;
+
+// Longest stretch of raw data printed for packets that can't be decoded
+const MAX_HEX: usize = 64;
+
+/// Pretty-prints a packet capture file written by the server.
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            println!("Usage: obelisk-dump <capture file>");
+            process::exit(2);
+        }
+    };
+
+    let data = match fs::read(&path) {
+        Ok(data) => Bytes::from(data),
+        Err(e) => {
+            println!("Unable to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let records = match capture::read(data) {
+        Ok(records) => records,
+        Err(e) => {
+            println!("Unable to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut start = None;
+    // Until the handshake says otherwise, assume the version packets are declared in
+    let mut version = Version::V1_13_2;
+    for record in records {
+        match record {
+            Ok(record) => {
+                if record.state == State::Handshake && record.direction == Direction::Inbound {
+                    if let Ok(handshake) = Handshake::decode(&mut record.data.clone().into_buf()) {
+                        version =
+                            Version::from_protocol(handshake.protocol_version.0).unwrap_or(version);
+                    }
+                }
+
+                let start = *start.get_or_insert(record.time);
+                print_record(&record, start, version);
+            }
+            Err(e) => {
+                println!("Capture is corrupt past this point: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn print_record(record: &Record, start: SystemTime, version: Version) {
+    let offset = record
+        .time
+        .duration_since(start)
+        .unwrap_or_default()
+        .as_millis();
+    let direction = match record.direction {
+        Direction::Inbound => "C->S",
+        Direction::Outbound => "S->C",
+    };
+
+    let packet = match record.describe(version) {
+        Some(Ok(packet)) => packet,
+        Some(Err(e)) => format!("<{}> {}", e, hex(&record.data)),
+        None => format!(
+            "<unknown, {} bytes> {}",
+            record.data.len(),
+            hex(&record.data)
+        ),
+    };
+
+    println!(
+        "{:>8}ms {} {:?} 0x{:02X} {}",
+        offset, direction, record.state, record.id, packet
+    );
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut hex: Vec<String> = data
+        .iter()
+        .take(MAX_HEX)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    if data.len() > MAX_HEX {
+        hex.push(String::from("..."));
+    }
+
+    hex.join(" ")
+}
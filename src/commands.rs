@@ -0,0 +1,101 @@
+use crate::net::version::Version;
+use crate::Obelisk;
+use std::sync::RwLock;
+
+type Command = fn(&RwLock<Obelisk>, &[&str]) -> String;
+
+/// Commands by name, with their usage as shown by `help`.
+const COMMANDS: &[(&str, &str, Command)] = &[
+    ("help", "help", help),
+    ("list", "list", list),
+    ("player", "player <name>", player),
+    ("stop", "stop", stop),
+    ("version", "version", version),
+];
+
+/// Runs a line typed at the console or sent over RCON, returning what it printed.
+pub fn run(server: &RwLock<Obelisk>, line: &str) -> String {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return String::new(),
+    };
+    let args: Vec<&str> = words.collect();
+
+    match COMMANDS
+        .iter()
+        .find(|(command, _, _)| command.eq_ignore_ascii_case(name))
+    {
+        Some((_, _, command)) => command(server, &args),
+        None => String::from("Unknown command. Type \"help\" for help."),
+    }
+}
+
+fn help(_server: &RwLock<Obelisk>, _args: &[&str]) -> String {
+    let usages: Vec<&str> = COMMANDS.iter().map(|(_, usage, _)| *usage).collect();
+    format!("Commands: {}", usages.join(", "))
+}
+
+fn list(server: &RwLock<Obelisk>, _args: &[&str]) -> String {
+    let server = server.read().unwrap();
+    let mut names: Vec<&str> = server
+        .players
+        .values()
+        .map(|player| player.username.as_str())
+        .collect();
+    names.sort_unstable();
+
+    format!(
+        "There are {} of a max {} players online: {}",
+        names.len(),
+        server.max_players,
+        names.join(", ")
+    )
+}
+
+fn player(server: &RwLock<Obelisk>, args: &[&str]) -> String {
+    let name = match args {
+        [name] => name,
+        _ => return String::from("Usage: player <name>"),
+    };
+
+    let server = server.read().unwrap();
+    let player = server
+        .players
+        .values()
+        .find(|player| player.username.eq_ignore_ascii_case(name));
+
+    match player {
+        Some(player) => format!(
+            "{} ({}) from {} on {} at {:.1}, {:.1}, {:.1}, {} ms ping, {} packets ({} bytes) waiting to send",
+            player.username,
+            player.uuid.to_hyphenated(),
+            player.address,
+            player.brand.as_ref().map_or("an unknown client", String::as_str),
+            player.location.x,
+            player.location.y,
+            player.location.z,
+            player.latency,
+            player.output.packets(),
+            player.output.bytes()
+        ),
+        None => format!("No player named {} is online", name),
+    }
+}
+
+fn stop(server: &RwLock<Obelisk>, _args: &[&str]) -> String {
+    if server.read().unwrap().shutdown.stop() {
+        String::from("Stopping the server")
+    } else {
+        String::from("The server is already stopping")
+    }
+}
+
+fn version(_server: &RwLock<Obelisk>, _args: &[&str]) -> String {
+    format!(
+        "This server is running Obelisk {} for Minecraft {} to {}",
+        env!("CARGO_PKG_VERSION"),
+        Version::OLDEST.name(),
+        crate::VERSION
+    )
+}
@@ -0,0 +1,289 @@
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+use std::fs;
+use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The least `max_queued_output` can be set to. Anything less couldn't hold the packets sent on
+/// joining, so every client would be disconnected for being too slow.
+pub const MIN_QUEUED_OUTPUT: usize = 64 * 1024;
+
+/// How the server is set up. Read from a JSON file with the same field names, where any field
+/// left out keeps its default and `null` turns off the optional ones. Durations are written
+/// like `"4s"` or `"1m 30s"`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to accept connections on. IPv6 addresses only take IPv6 connections, so list
+    /// `0.0.0.0` and `::` together to accept both.
+    pub listen: Vec<SocketAddr>,
+    /// Packets at least this many bytes long are zlib compressed once the client has logged in.
+    /// `None` disables compression entirely.
+    pub compression_threshold: Option<usize>,
+    /// Authenticates players with the session server and encrypts their connection.
+    pub online_mode: bool,
+    /// Expect a PROXY protocol (v1 or v2) header on every connection, giving the address of the
+    /// client behind a load balancer.
+    pub proxy_protocol: bool,
+    /// Addresses allowed to send PROXY headers. Other connections are refused while
+    /// `proxy_protocol` is on.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// How long an address has to wait between logins. Server list pings aren't throttled.
+    /// With `forwarding` on this is the player's address as the proxy forwards it, so players
+    /// joining through the same proxy don't hold each other up.
+    #[serde(deserialize_with = "optional_duration")]
+    pub connection_throttle: Option<Duration>,
+    /// Most connections one address can have open at once. Behind a PROXY protocol balancer
+    /// this counts the client's address rather than the balancer's. Not enforced with
+    /// `forwarding` on, since every player connects from the proxy's address.
+    pub max_connections_per_ip: Option<usize>,
+    /// Most connections the server will have open at once.
+    pub max_connections: Option<usize>,
+    /// Most bytes of packets that can be waiting to go out to a client. A client that falls
+    /// this far behind is disconnected for being too slow. At least `MIN_QUEUED_OUTPUT`.
+    #[serde(deserialize_with = "queued_output")]
+    pub max_queued_output: usize,
+    /// Writes every packet sent to and received from each client to its own file in this
+    /// directory, to be read with `obelisk-dump`. Only meant for debugging.
+    pub capture_dir: Option<PathBuf>,
+    /// Message shown under the server's name in server lists.
+    pub motd: Motd,
+    /// A 64x64 PNG shown as the server's icon in server lists, if the file exists.
+    pub favicon: Option<PathBuf>,
+    /// What server lists show when hovering over the player count.
+    pub player_sample: PlayerSample,
+    /// Answers GameSpy4 UDP queries from server lists on this address. `None` turns query off.
+    pub query: Option<SocketAddr>,
+    /// Runs console commands sent over RCON. `None` turns RCON off.
+    pub rcon: Option<Rcon>,
+    /// Takes player info from a proxy in front of the server instead of authenticating players
+    /// here. The proxy should be the only way to reach the server while this is on.
+    pub forwarding: Forwarding,
+    /// Disconnect reason players are shown when the server stops.
+    pub shutdown_message: String,
+    /// How long stopping waits for clients to be sent everything queued for them before
+    /// closing their connections anyway.
+    #[serde(deserialize_with = "duration")]
+    pub shutdown_timeout: Duration,
+    /// Saves where each player is when they leave to a file in this directory, and puts them
+    /// back there when they next join. `None` spawns everyone at the spawn location.
+    pub player_data: Option<PathBuf>,
+    /// Block states as the vanilla server's data generator writes them to `blocks.json`. Nothing
+    /// is loaded unless this is set, and the server won't start if it's set but can't be read.
+    pub block_palette: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Motd {
+    /// Plain text, which can use `§` formatting codes.
+    Text(String),
+    /// A chat component, such as `{"text": "Hello", "color": "gold"}`. Clients that only
+    /// understand text get it flattened into formatting codes.
+    Component(Value),
+}
+
+/// Written as `"online"`, `"hidden"` or `{"custom": ["line", ...]}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerSample {
+    /// Some of the players online.
+    Online,
+    /// No sample, only the counts.
+    Hidden,
+    /// These lines in place of the players.
+    Custom(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rcon {
+    pub address: SocketAddr,
+    /// Has to be given before any commands are run. RCON won't start without one.
+    pub password: String,
+}
+
+/// Written as `"none"`, `"bungeecord"` or `{"velocity": {"secret": "..."}}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Forwarding {
+    None,
+    /// BungeeCord's legacy IP forwarding, appended to the handshake's server address.
+    BungeeCord,
+    /// Velocity's modern forwarding, signed with a secret shared with the proxy.
+    Velocity {
+        secret: String,
+    },
+}
+
+impl Config {
+    /// Reads the config from a JSON file.
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// The defaults, except for what reaches for files in the working directory and the login
+    /// throttle, so tests can run side by side and log in as often as they like.
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub fn for_tests() -> Config {
+        Config {
+            connection_throttle: None,
+            favicon: None,
+            player_data: None,
+            ..Config::default()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 25565)],
+            compression_threshold: Some(256),
+            online_mode: true,
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+            connection_throttle: Some(Duration::from_millis(4000)),
+            max_connections_per_ip: None,
+            max_connections: None,
+            max_queued_output: 8 * 1024 * 1024,
+            capture_dir: None,
+            motd: Motd::Text(String::from("An Obelisk server")),
+            favicon: Some(PathBuf::from("server-icon.png")),
+            player_sample: PlayerSample::Online,
+            query: None,
+            rcon: None,
+            forwarding: Forwarding::None,
+            shutdown_message: String::from("Server closed"),
+            shutdown_timeout: Duration::from_secs(10),
+            player_data: Some(PathBuf::from("playerdata")),
+            block_palette: None,
+        }
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map_err(de::Error::custom)
+}
+
+fn queued_output<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let bytes = usize::deserialize(deserializer)?;
+    if bytes < MIN_QUEUED_OUTPUT {
+        return Err(de::Error::custom(format!(
+            "max_queued_output has to be at least {} bytes",
+            MIN_QUEUED_OUTPUT
+        )));
+    }
+
+    Ok(bytes)
+}
+
+fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) => humantime::parse_duration(&text)
+            .map(Some)
+            .map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<Config, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn left_out_fields_keep_their_defaults() {
+        let config = parse(r#"{"online_mode": false}"#).unwrap();
+        assert!(!config.online_mode);
+        assert_eq!(config.listen, Config::default().listen);
+        assert_eq!(config.compression_threshold, Some(256));
+        assert_eq!(config.connection_throttle, Some(Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn every_section_is_read() {
+        let config = parse(
+            r#"{
+                "listen": ["0.0.0.0:25565", "[::]:25565"],
+                "compression_threshold": null,
+                "proxy_protocol": true,
+                "trusted_proxies": ["10.0.0.1"],
+                "connection_throttle": "1m 30s",
+                "max_connections_per_ip": 3,
+                "max_connections": 100,
+                "motd": {"text": "Hello", "color": "gold"},
+                "favicon": "icon.png",
+                "player_sample": {"custom": ["Welcome"]},
+                "query": "0.0.0.0:25565",
+                "rcon": {"address": "127.0.0.1:25575", "password": "hunter2"},
+                "shutdown_timeout": "30s",
+                "player_data": null,
+                "block_palette": "data/blocks.json",
+                "forwarding": {"velocity": {"secret": "shh"}}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.compression_threshold, None);
+        assert!(config.proxy_protocol);
+        assert_eq!(config.trusted_proxies, vec![IpAddr::from([10, 0, 0, 1])]);
+        assert_eq!(config.connection_throttle, Some(Duration::from_secs(90)));
+        assert_eq!(config.max_connections_per_ip, Some(3));
+        assert_eq!(config.max_connections, Some(100));
+        assert!(matches!(config.motd, Motd::Component(ref motd) if motd["color"] == "gold"));
+        assert_eq!(config.favicon, Some(PathBuf::from("icon.png")));
+        assert!(
+            matches!(config.player_sample, PlayerSample::Custom(ref lines) if lines.len() == 1)
+        );
+        assert_eq!(config.query, Some("0.0.0.0:25565".parse().unwrap()));
+        assert_eq!(config.rcon.unwrap().password, "hunter2");
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(30));
+        assert_eq!(config.player_data, None);
+        assert_eq!(
+            config.block_palette,
+            Some(PathBuf::from("data/blocks.json"))
+        );
+        assert!(
+            matches!(config.forwarding, Forwarding::Velocity { ref secret } if secret == "shh")
+        );
+    }
+
+    #[test]
+    fn text_motds_and_named_options_are_read() {
+        let config = parse(
+            r#"{
+                "motd": "§6Hello",
+                "player_sample": "hidden",
+                "forwarding": "bungeecord",
+                "connection_throttle": null
+            }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(config.motd, Motd::Text(ref motd) if motd == "§6Hello"));
+        assert!(matches!(config.player_sample, PlayerSample::Hidden));
+        assert!(matches!(config.forwarding, Forwarding::BungeeCord));
+        assert_eq!(config.connection_throttle, None);
+    }
+
+    #[test]
+    fn mistakes_are_rejected() {
+        assert!(parse(r#"{"onlne_mode": false}"#).is_err());
+        assert!(parse(r#"{"listen": ["localhost"]}"#).is_err());
+        assert!(parse(r#"{"connection_throttle": 4}"#).is_err());
+        assert!(parse(r#"{"connection_throttle": "soon"}"#).is_err());
+        assert!(parse(r#"{"shutdown_timeout": null}"#).is_err());
+        assert!(parse(r#"{"max_queued_output": 16}"#).is_err());
+    }
+}
@@ -1,11 +1,134 @@
-use crate::world::Location;
+use crate::net::output::QueueStats;
+use crate::world::{Location, World};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use uuid::Uuid;
 
 pub struct Player {
     pub uuid: Uuid,
     pub username: String,
     pub entity_id: i32,
+    pub address: SocketAddr,
     pub location: Location,
+    pub properties: Vec<ProfileProperty>,
+    pub on_ground: bool,
+    /// Smoothed keep alive round trip time in milliseconds.
+    pub latency: u32,
+    pub settings: Settings,
+    /// What the client calls itself over `minecraft:brand`, such as "vanilla".
+    pub brand: Option<String>,
+    /// Output waiting to be sent to the player's client.
+    pub output: Arc<QueueStats>,
+}
+
+/// What's kept of a player between sessions.
+#[derive(Serialize, Deserialize)]
+struct SavedPlayer {
+    world: String,
+    x: f64,
+    y: f64,
+    z: f64,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Player {
+    /// Writes the player's location to their file in `dir`.
+    pub fn save(&self, dir: &Path) -> Result<(), Error> {
+        let world = match self.location.world.upgrade() {
+            Some(world) => world.name.clone(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "Player's world was unloaded",
+                ))
+            }
+        };
+
+        let saved = SavedPlayer {
+            world,
+            x: self.location.x,
+            y: self.location.y,
+            z: self.location.z,
+            yaw: self.location.yaw,
+            pitch: self.location.pitch,
+        };
+
+        fs::create_dir_all(dir)?;
+        // Written alongside and renamed over the old file, so a crash can't leave half of one
+        let path = data_path(dir, &self.uuid);
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(&saved)?)?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+/// Reads where a player was saved in `dir`, or `None` if they haven't been or their world is
+/// gone.
+pub fn load_location(
+    dir: &Path,
+    uuid: &Uuid,
+    worlds: &[Arc<World>],
+) -> Result<Option<Location>, Error> {
+    let data = match fs::read(data_path(dir, uuid)) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let saved: SavedPlayer = serde_json::from_slice(&data)?;
+    let world = match worlds.iter().find(|world| world.name == saved.world) {
+        Some(world) => world,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Location {
+        x: saved.x,
+        y: saved.y,
+        z: saved.z,
+        yaw: saved.yaw,
+        pitch: saved.pitch,
+        world: Arc::downgrade(world),
+    }))
+}
+
+fn data_path(dir: &Path, uuid: &Uuid) -> PathBuf {
+    dir.join(format!("{}.json", uuid.to_hyphenated()))
+}
+
+/// Options the client reports through Client Settings.
+pub struct Settings {
+    pub locale: String,
+    pub view_distance: i8,
+    pub chat_mode: i32,
+    pub chat_colors: bool,
+    pub skin_parts: u8,
+    pub main_hand: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            locale: String::from("en_us"),
+            view_distance: 10,
+            chat_mode: 0,
+            chat_colors: true,
+            skin_parts: 0x7F,
+            main_hand: 1,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default)]
+    pub signature: Option<String>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
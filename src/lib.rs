@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::entities::player;
+use crate::entities::player::{Player, ProfileProperty};
+use crate::net::channels;
+use crate::net::output::QueueStats;
+use crate::net::session::SessionVerifier;
+use crate::net::shutdown::Shutdown;
+use crate::net::status;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub mod commands;
+pub mod config;
+pub mod entities;
+pub mod net;
+pub mod world;
+
+/// The newest version clients can join with. Older ones back to 1.13 can too, see
+/// `net::version`.
+pub const VERSION: &str = "1.14.4";
+
+pub struct Obelisk {
+    pub players: HashMap<Uuid, Player>,
+    /// Shown in server lists. The status takes it when the server is made, so changing it later
+    /// only reaches queries and the `list` command.
+    pub max_players: u32,
+    pub worlds: Vec<Arc<world::World>>,
+    pub spawn_location: world::Location,
+    pub config: Config,
+    pub session_verifier: Arc<dyn SessionVerifier>,
+    pub channels: channels::Registry,
+    pub shutdown: Shutdown,
+    pub status: Arc<status::Cache>,
+}
+
+impl Obelisk {
+    /// A server with one creative overworld and nobody online. Online mode logins are checked
+    /// with `session_verifier`.
+    pub fn new(config: Config, session_verifier: Arc<dyn SessionVerifier>) -> Obelisk {
+        let world = Arc::new(world::World {
+            name: String::from("world"),
+            gamemode: player::Gamemode::Creative,
+            hardcore: false,
+            dimension: world::Dimension::Overworld,
+            difficulty: world::Difficulty::Peaceful,
+            level_type: world::LevelType::Default,
+        });
+
+        let spawn_location = world::Location {
+            x: 0.0,
+            y: 128.0,
+            z: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            world: Arc::downgrade(&world),
+        };
+
+        let max_players = 10;
+        Obelisk {
+            players: HashMap::new(),
+            max_players,
+            worlds: vec![world],
+            spawn_location,
+            status: Arc::new(status::Cache::new(&config, max_players)),
+            config,
+            session_verifier,
+            channels: channels::Registry::default(),
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    pub fn create_player(
+        &mut self,
+        uuid: Uuid,
+        username: String,
+        address: SocketAddr,
+        properties: Vec<ProfileProperty>,
+        output: Arc<QueueStats>,
+    ) -> &Player {
+        let location = self.saved_location(&uuid, &username);
+        self.players.insert(
+            uuid,
+            Player {
+                uuid,
+                username,
+                entity_id: rand::random(),
+                address,
+                location,
+                properties,
+                on_ground: false,
+                latency: 0,
+                settings: player::Settings::default(),
+                brand: None,
+                output,
+            },
+        );
+        self.status.update_players(&self.players);
+
+        self.players.get(&uuid).unwrap()
+    }
+
+    /// Takes a player off the server, saving them first.
+    pub fn remove_player(&mut self, uuid: &Uuid) -> Option<Player> {
+        let player = self.players.remove(uuid)?;
+        self.status.update_players(&self.players);
+        if let Some(ref dir) = self.config.player_data {
+            if let Err(e) = player.save(dir) {
+                println!("Unable to save {}: {}", player.username, e);
+            }
+        }
+
+        Some(player)
+    }
+
+    // Where the player left off last time, or spawn if they're new or it can't be read
+    fn saved_location(&self, uuid: &Uuid, username: &str) -> world::Location {
+        let saved = match self.config.player_data {
+            Some(ref dir) => player::load_location(dir, uuid, &self.worlds),
+            None => Ok(None),
+        };
+
+        match saved {
+            Ok(Some(location)) => location,
+            Ok(None) => self.spawn_location.clone(),
+            Err(e) => {
+                println!("Unable to load {}: {}", username, e);
+                self.spawn_location.clone()
+            }
+        }
+    }
+}
@@ -1,73 +1,96 @@
-use crate::entities::player;
-use crate::entities::player::Player;
-use crate::world::palette::PaletteEntry;
-use crate::world::Location;
+use obelisk::commands;
+use obelisk::config::Config;
+use obelisk::net;
+use obelisk::net::session::HttpSessionVerifier;
+use obelisk::world::palette::PaletteEntry;
+use obelisk::Obelisk;
 use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{Arc, RwLock};
-use uuid::Uuid;
+use std::thread;
 
-pub mod entities;
-pub mod net;
-pub mod world;
-
-pub const VERSION: &str = "1.13.2";
-pub const PROTOCOL: u16 = 404;
-
-pub struct Obelisk {
-    pub players: HashMap<Uuid, Player>,
-    pub max_players: u32,
-    pub worlds: Vec<Arc<world::World>>,
-    pub spawn_location: world::Location,
-}
+// Read if it's there and no other file is given on the command line
+const CONFIG_FILE: &str = "obelisk.json";
 
 fn main() {
-    let world = Arc::new(world::World {
-        gamemode: player::Gamemode::Creative,
-        hardcore: false,
-        dimension: world::Dimension::Overworld,
-        difficulty: world::Difficulty::Peaceful,
-        level_type: world::LevelType::Default,
-    });
-
-    let spawn_location = Location {
-        x: 0.0,
-        y: 128.0,
-        z: 0.0,
-        world: Arc::downgrade(&world),
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
     };
 
-    let mut worlds = Vec::new();
-    worlds.push(world);
+    if let Some(ref path) = config.block_palette {
+        if let Err(e) = load_palette(path) {
+            println!(
+                "Unable to load the block palette from {}: {}",
+                path.display(),
+                e
+            );
+            process::exit(1);
+        }
+    }
 
-    let mut obelisk = Obelisk {
-        players: HashMap::new(),
-        max_players: 10,
-        worlds,
-        spawn_location,
+    let session_verifier = match HttpSessionVerifier::mojang() {
+        Ok(session_verifier) => session_verifier,
+        Err(e) => {
+            println!("Unable to create the session verifier: {}", e);
+            process::exit(1);
+        }
     };
+    let obelisk = Arc::new(RwLock::new(Obelisk::new(
+        config,
+        Arc::new(session_verifier),
+    )));
 
-    let mut obelisk = Arc::new(RwLock::new(obelisk));
+    read_console(obelisk.clone());
 
-    net::start(obelisk.clone());
+    if let Err(e) = net::start(obelisk.clone()) {
+        println!("Unable to start the server: {}", e);
+        process::exit(1);
+    }
 
-    let palette_json = fs::read_to_string("/home/rafi/blocks.json").expect("Could not read file");
-    let palette: HashMap<String, PaletteEntry> =
-        serde_json::from_str(&palette_json).expect("Failed to parse json");
+    println!("Server stopped");
 }
 
-impl Obelisk {
-    pub fn create_player(&mut self, uuid: Uuid, username: String) -> &Player {
-        self.players.insert(
-            uuid.clone(),
-            Player {
-                uuid: uuid.clone(),
-                username,
-                entity_id: rand::random(),
-                location: self.spawn_location.clone(),
-            },
-        );
+fn load_palette(path: &Path) -> Result<HashMap<String, PaletteEntry>, io::Error> {
+    let palette_json = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&palette_json)?)
+}
 
-        self.players.get(&uuid).unwrap()
-    }
+/// Loads the config from the file given as the only argument, or `obelisk.json` if there isn't
+/// one. Without either the defaults are used.
+fn load_config() -> Result<Config, String> {
+    let mut args = env::args_os().skip(1);
+    let path = match (args.next(), args.next()) {
+        (Some(path), None) => PathBuf::from(path),
+        (None, _) if PathBuf::from(CONFIG_FILE).exists() => PathBuf::from(CONFIG_FILE),
+        (None, _) => return Ok(Config::default()),
+        (Some(_), Some(_)) => return Err(String::from("Usage: obelisk [config file]")),
+    };
+
+    Config::load(&path).map_err(|e| format!("Unable to load {}: {}", path.display(), e))
+}
+
+/// Runs commands typed into the console on a thread of their own, since reading stdin blocks.
+fn read_console(server: Arc<RwLock<Obelisk>>) {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            let output = commands::run(&server, &line);
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+    });
 }
@@ -0,0 +1,238 @@
+use super::codec::{self, ProtocolError};
+use super::packets::{handshake, login, play, play_1_14, status};
+use super::version::Version;
+use super::Packet;
+use bytes::{Buf, Bytes, IntoBuf};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Error, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Starts every capture file, ahead of its records.
+pub const MAGIC: &[u8] = b"OBELISK CAPTURE 1\n";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// The connection state a packet was sent in, which decides what its id means.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Handshake,
+    Status,
+    Login,
+    Play,
+}
+
+/// One packet sent or received, after decryption and decompression.
+pub struct Record {
+    pub time: SystemTime,
+    pub direction: Direction,
+    pub state: State,
+    pub id: i32,
+    pub data: Bytes,
+}
+
+/// Writes the packets of one connection to a file. Each record is the time in milliseconds
+/// since the Unix epoch as a Long, the direction and state as a Byte each, the packet id as a
+/// VarInt and then the packet data as a byte array.
+pub struct Capture {
+    file: BufWriter<File>,
+}
+
+impl Capture {
+    /// Creates a capture file in `dir` named after the connection's address and start time.
+    pub fn create(dir: &Path, address: &SocketAddr) -> Result<Capture, Error> {
+        // Colons aren't allowed in file names everywhere so IPv6 addresses use dots instead
+        let name = format!(
+            "{}-{}-{}.cap",
+            millis(SystemTime::now()),
+            address.ip().to_string().replace(':', "."),
+            address.port()
+        );
+
+        let mut file = BufWriter::new(File::create(dir.join(name))?);
+        file.write_all(MAGIC)?;
+        Ok(Capture { file })
+    }
+
+    pub fn write(
+        &mut self,
+        direction: Direction,
+        state: State,
+        packet: &Packet,
+    ) -> Result<(), Error> {
+        let mut record = Vec::with_capacity(packet.data.len() + 16);
+        codec::write_long(&mut record, millis(SystemTime::now()) as i64);
+        codec::write_ubyte(&mut record, direction as u8);
+        codec::write_ubyte(&mut record, state as u8);
+        codec::write_varint(&mut record, packet.id);
+        codec::write_byte_array(&mut record, &packet.data);
+
+        self.file.write_all(&record)
+    }
+}
+
+/// Reads the records back out of a capture file's contents.
+pub fn read(data: Bytes) -> Result<Reader, Error> {
+    if !data.starts_with(MAGIC) {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a capture file"));
+    }
+
+    Ok(Reader {
+        buf: data.slice_from(MAGIC.len()).into_buf(),
+    })
+}
+
+pub struct Reader {
+    buf: Cursor<Bytes>,
+}
+
+impl Iterator for Reader {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.buf.has_remaining() {
+            return None;
+        }
+
+        let record = read_record(&mut self.buf);
+        if record.is_err() {
+            // Nothing after a bad record can be trusted
+            self.buf.set_position(self.buf.get_ref().len() as u64);
+        }
+
+        Some(record)
+    }
+}
+
+fn read_record<B: Buf>(buf: &mut B) -> Result<Record, Error> {
+    let time = UNIX_EPOCH + Duration::from_millis(codec::read_long(buf)? as u64);
+    let direction = match codec::read_ubyte(buf)? {
+        0 => Direction::Inbound,
+        1 => Direction::Outbound,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid direction")),
+    };
+    let state = match codec::read_ubyte(buf)? {
+        0 => State::Handshake,
+        1 => State::Status,
+        2 => State::Login,
+        3 => State::Play,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid state")),
+    };
+    let id = codec::read_varint(buf)?;
+    let data = Bytes::from(codec::read_byte_array(buf)?);
+
+    Ok(Record {
+        time,
+        direction,
+        state,
+        id,
+        data,
+    })
+}
+
+impl Record {
+    /// Decodes the packet for debugging, or returns `None` if its id isn't one we know in the
+    /// record's state and direction. `version` is the one the client gave in its handshake.
+    pub fn describe(&self, version: Version) -> Option<Result<String, ProtocolError>> {
+        let buf = &mut self.data.clone().into_buf();
+
+        if self.state == State::Play && version >= Version::V1_14 {
+            let changed = match self.direction {
+                Direction::Inbound => play_1_14::serverbound::describe(self.id, buf),
+                Direction::Outbound => play_1_14::clientbound::describe(self.id, buf),
+            };
+
+            if changed.is_some() {
+                return changed;
+            }
+        }
+
+        let id = match self.state {
+            State::Play => version.play_id(self.direction == Direction::Outbound, self.id)?,
+            _ => self.id,
+        };
+
+        match (self.state, self.direction) {
+            (State::Handshake, Direction::Inbound) => handshake::serverbound::describe(id, buf),
+            (State::Handshake, Direction::Outbound) => None,
+            (State::Status, Direction::Inbound) => status::serverbound::describe(id, buf),
+            (State::Status, Direction::Outbound) => status::clientbound::describe(id, buf),
+            (State::Login, Direction::Inbound) => login::serverbound::describe(id, buf),
+            (State::Login, Direction::Outbound) => login::clientbound::describe(id, buf),
+            (State::Play, Direction::Inbound) => play::serverbound::describe(id, buf),
+            (State::Play, Direction::Outbound) => play::clientbound::describe(id, buf),
+        }
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn written_records_read_back() {
+        let dir = std::env::temp_dir().join(format!("obelisk-capture-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let address = "[::1]:51234".parse().unwrap();
+
+        let mut capture = Capture::create(&dir, &address).unwrap();
+        let handshake = Packet::new(0x00, Bytes::from(&b"\xBC\x02\x09localhost\x63\xDD\x01"[..]));
+        let chat = Packet::new(0x0E, Bytes::from(&b"\x05hello"[..]));
+        capture
+            .write(Direction::Inbound, State::Handshake, &handshake)
+            .unwrap();
+        capture
+            .write(Direction::Outbound, State::Play, &chat)
+            .unwrap();
+        drop(capture);
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let data = fs::read(&files[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with("-..1-51234.cap"));
+
+        let records: Vec<Record> = read(Bytes::from(data))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Inbound);
+        assert_eq!(records[0].state, State::Handshake);
+        assert_eq!((records[0].id, &records[0].data), (0x00, &handshake.data));
+        assert_eq!(records[1].direction, Direction::Outbound);
+        assert_eq!(records[1].state, State::Play);
+        assert_eq!((records[1].id, &records[1].data), (0x0E, &chat.data));
+        assert!(records[0].describe(Version::LATEST).unwrap().is_ok());
+    }
+
+    #[test]
+    fn reading_stops_at_a_bad_record() {
+        let mut data = MAGIC.to_vec();
+        codec::write_long(&mut data, 0);
+        codec::write_ubyte(&mut data, 0);
+        codec::write_ubyte(&mut data, 9);
+        data.extend_from_slice(&[0; 8]);
+
+        let mut reader = read(Bytes::from(data)).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+        assert!(read(Bytes::from(&b"not a capture"[..])).is_err());
+    }
+}
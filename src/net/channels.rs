@@ -0,0 +1,204 @@
+use super::{codec, PlayerSocket, PluginMessage};
+use bytes::{Bytes, IntoBuf};
+use std::collections::{HashMap, HashSet};
+use tokio::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+/// Receives the data of a plugin message sent on a channel it's subscribed to.
+pub type Handler = fn(&mut PlayerSocket, &Uuid, Bytes) -> Result<(), Error>;
+
+const BRAND: &str = "Obelisk";
+// Same limit as Bukkit, so a client can't register channels without end
+const MAX_REGISTERED: usize = 128;
+
+/// Handlers for plugin channels, keyed by namespaced channel such as `minecraft:brand`.
+pub struct Registry {
+    handlers: HashMap<String, Handler>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry {
+            handlers: HashMap::new(),
+        };
+
+        registry.subscribe("minecraft:brand", read_brand);
+        registry.subscribe("minecraft:register", register);
+        registry.subscribe("minecraft:unregister", unregister);
+        registry
+    }
+}
+
+impl Registry {
+    /// Routes messages clients send on `channel` to `handler`, in place of any handler it had.
+    pub fn subscribe(&mut self, channel: &str, handler: Handler) {
+        self.handlers.insert(channel.to_string(), handler);
+    }
+
+    fn handler(&self, channel: &str) -> Option<Handler> {
+        self.handlers.get(channel).cloned()
+    }
+
+    /// Channels outside the `minecraft` namespace, which clients need telling about.
+    fn custom_channels(&self) -> Vec<&str> {
+        self.handlers
+            .keys()
+            .map(|channel| channel.as_str())
+            .filter(|channel| !channel.starts_with("minecraft:"))
+            .collect()
+    }
+}
+
+/// Routes a plugin message from the client to the channel's handler. Messages on channels
+/// nothing is subscribed to are dropped.
+pub fn receive(
+    socket: &mut PlayerSocket,
+    uuid: &Uuid,
+    channel: &str,
+    data: Bytes,
+) -> Result<(), Error> {
+    // Copied out so the handler is free to lock the server itself
+    let handler = socket.server.read().unwrap().channels.handler(channel);
+
+    match handler {
+        Some(handler) => handler(socket, uuid, data),
+        None => Ok(()),
+    }
+}
+
+/// Sends a plugin message if the client registered its channel. Clients always understand the
+/// `minecraft` namespace so those go out regardless.
+pub fn send(socket: &mut PlayerSocket, message: PluginMessage) {
+    if message.namespace == "minecraft" || socket.channels.contains(&message.identifier()) {
+        socket.queue(message.into());
+    }
+}
+
+/// Tells a client that just joined the server's brand and which custom channels it listens on.
+pub fn send_server_channels(socket: &mut PlayerSocket) {
+    let mut brand = Vec::new();
+    codec::write_string(&mut brand, BRAND);
+    send(
+        socket,
+        PluginMessage::new_minecraft(String::from("brand"), Bytes::from(brand)),
+    );
+
+    let channels = socket
+        .server
+        .read()
+        .unwrap()
+        .channels
+        .custom_channels()
+        .join("\0");
+    if !channels.is_empty() {
+        send(
+            socket,
+            PluginMessage::new_minecraft(String::from("register"), Bytes::from(channels)),
+        );
+    }
+}
+
+fn read_brand(socket: &mut PlayerSocket, uuid: &Uuid, data: Bytes) -> Result<(), Error> {
+    let brand = codec::read_string(&mut data.into_buf(), codec::MAX_STRING_LENGTH)?;
+    let mut server = socket.server.write().unwrap();
+    if let Some(player) = server.players.get_mut(uuid) {
+        player.brand = Some(brand);
+    }
+
+    Ok(())
+}
+
+fn register(socket: &mut PlayerSocket, _uuid: &Uuid, data: Bytes) -> Result<(), Error> {
+    add_channels(&mut socket.channels, &data)
+}
+
+fn unregister(socket: &mut PlayerSocket, _uuid: &Uuid, data: Bytes) -> Result<(), Error> {
+    remove_channels(&mut socket.channels, &data)
+}
+
+fn add_channels(registered: &mut HashSet<String>, data: &[u8]) -> Result<(), Error> {
+    for channel in read_channels(data)? {
+        if !registered.contains(&channel) && registered.len() >= MAX_REGISTERED {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Too many plugin channels registered",
+            ));
+        }
+
+        registered.insert(channel);
+    }
+
+    Ok(())
+}
+
+fn remove_channels(registered: &mut HashSet<String>, data: &[u8]) -> Result<(), Error> {
+    for channel in read_channels(data)? {
+        registered.remove(&channel);
+    }
+
+    Ok(())
+}
+
+// Register and unregister list channels separated by null characters
+fn read_channels(data: &[u8]) -> Result<Vec<String>, Error> {
+    let channels = std::str::from_utf8(data)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid channel name"))?;
+
+    Ok(channels
+        .split('\0')
+        .filter(|channel| !channel.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(count: usize) -> String {
+        (0..count)
+            .map(|i| format!("test:channel{}", i))
+            .collect::<Vec<_>>()
+            .join("\0")
+    }
+
+    #[test]
+    fn registering_stops_at_the_limit() {
+        let mut registered = HashSet::new();
+        add_channels(&mut registered, channels(MAX_REGISTERED).as_bytes()).unwrap();
+        assert_eq!(registered.len(), MAX_REGISTERED);
+
+        // Channels already registered don't count again
+        add_channels(&mut registered, b"test:channel0\0test:channel1").unwrap();
+        assert!(add_channels(&mut registered, b"test:one_more").is_err());
+        assert_eq!(registered.len(), MAX_REGISTERED);
+    }
+
+    #[test]
+    fn unregistering_makes_room() {
+        let mut registered = HashSet::new();
+        add_channels(&mut registered, channels(MAX_REGISTERED).as_bytes()).unwrap();
+
+        remove_channels(&mut registered, b"test:channel5\0test:unknown").unwrap();
+        assert_eq!(registered.len(), MAX_REGISTERED - 1);
+        add_channels(&mut registered, b"test:one_more").unwrap();
+        assert!(registered.contains("test:one_more"));
+    }
+
+    #[test]
+    fn channel_lists_are_split_on_nulls() {
+        let mut registered = HashSet::new();
+        add_channels(&mut registered, b"\0a:b\0\0c:d\0").unwrap();
+        assert_eq!(registered.len(), 2);
+        assert!(add_channels(&mut registered, b"a:\xFF").is_err());
+    }
+
+    #[test]
+    fn only_custom_channels_are_announced() {
+        let mut registry = Registry::default();
+        assert!(registry.custom_channels().is_empty());
+
+        registry.subscribe("obelisk:test", read_brand);
+        assert_eq!(registry.custom_channels(), vec!["obelisk:test"]);
+    }
+}
@@ -0,0 +1,395 @@
+use super::codec::{self, ProtocolError};
+use super::encryption;
+use super::packets::play::{clientbound, serverbound};
+use super::packets::{handshake, login, status};
+use super::packets::{ClientboundPacket, Remaining, ServerboundPacket, VarInt};
+use super::version::Version;
+use super::{Packet, PacketCodec};
+use bytes::{Bytes, BytesMut, IntoBuf};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::rand_core::OsRng;
+use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use tokio::codec::{Decoder, Encoder};
+use uuid::Uuid;
+
+// Long enough for a busy server, short enough that a test against a stuck one fails
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_SIZE: usize = 8 * 1024;
+const BRAND: &str = "obelisk-client";
+
+/// The state of the connection, as the client sees it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Handshake,
+    Status,
+    Login,
+    Play,
+}
+
+/// The server's answer to a server list ping.
+pub struct Ping {
+    pub status: Value,
+    pub latency: Duration,
+}
+
+/// A headless client for driving a server over the network, from tests or bots. Every call
+/// blocks until the server answers or the timeout passes. Online mode logins are encrypted,
+/// but nothing tells a session server the player is joining, so the server has to be checking
+/// with one that lets them in regardless.
+pub struct Client {
+    stream: TcpStream,
+    address: SocketAddr,
+    codec: PacketCodec,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    version: Version,
+    state: State,
+    // Play packets read while waiting for something else, handed out by `recv` first
+    pending: VecDeque<Packet>,
+    server_hash: Option<String>,
+    // Sent in the handshake instead of the server's IP, if set
+    server_address: Option<String>,
+}
+
+impl Client {
+    pub fn connect(address: SocketAddr, version: Version) -> Result<Client, Error> {
+        let stream = TcpStream::connect_timeout(&address, DEFAULT_TIMEOUT)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        Ok(Client {
+            stream,
+            address,
+            codec: PacketCodec::client(),
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            version,
+            state: State::Handshake,
+            pending: VecDeque::new(),
+            server_hash: None,
+            server_address: None,
+        })
+    }
+
+    /// How long to wait on the server before giving up with `WouldBlock` or `TimedOut`.
+    /// `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The hash a session server would be asked about, once an online mode login has been
+    /// encrypted.
+    pub fn server_hash(&self) -> Option<&str> {
+        self.server_hash.as_deref()
+    }
+
+    /// What the handshake says the client connected to, in place of the server's IP. This is
+    /// where BungeeCord forwards player info.
+    pub fn set_server_address(&mut self, server_address: String) {
+        self.server_address = Some(server_address);
+    }
+
+    /// Sends the handshake, moving on to either Status or Login.
+    pub fn handshake(&mut self, next: State) -> Result<(), Error> {
+        let next_state = match next {
+            State::Status => 1,
+            State::Login => 2,
+            State::Handshake | State::Play => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Handshakes only lead to Status or Login",
+                ))
+            }
+        };
+
+        self.send(&handshake::serverbound::Handshake {
+            protocol_version: VarInt(self.version.protocol()),
+            server_address: self
+                .server_address
+                .clone()
+                .unwrap_or_else(|| self.address.ip().to_string()),
+            server_port: self.address.port(),
+            next_state: VarInt(next_state),
+        })?;
+        self.state = next;
+
+        Ok(())
+    }
+
+    /// Asks for the server's status then times a ping, as the server list does. The connection
+    /// is finished with after this.
+    pub fn ping(&mut self) -> Result<Ping, Error> {
+        self.handshake(State::Status)?;
+        self.send(&status::serverbound::Request {})?;
+        let response: status::clientbound::Response = self.expect()?;
+        let status = serde_json::from_str(&response.json)?;
+
+        let payload = rand::random();
+        let sent = Instant::now();
+        self.send(&status::serverbound::Ping { payload })?;
+        let pong: status::clientbound::Pong = self.expect()?;
+        if pong.payload != payload {
+            return Err(Error::new(ErrorKind::InvalidData, "Pong didn't match ping"));
+        }
+
+        Ok(Ping {
+            status,
+            latency: sent.elapsed(),
+        })
+    }
+
+    /// Logs in as `username`, returning the UUID the server gave the player.
+    pub fn login(&mut self, username: &str) -> Result<Uuid, Error> {
+        self.handshake(State::Login)?;
+        self.send(&login::serverbound::LoginStart {
+            username: username.to_string(),
+        })?;
+
+        loop {
+            let packet = self.read_packet()?;
+            match packet.id {
+                login::clientbound::Disconnect::ID => {
+                    let disconnect: login::clientbound::Disconnect = decode(&packet)?;
+                    return Err(disconnected(&disconnect.reason));
+                }
+                login::clientbound::EncryptionRequest::ID => {
+                    let request: login::clientbound::EncryptionRequest = decode(&packet)?;
+                    self.encrypt(request)?;
+                }
+                login::clientbound::SetCompression::ID => {
+                    let compression: login::clientbound::SetCompression = decode(&packet)?;
+                    // A negative threshold turns compression back off
+                    let threshold = compression.threshold.0;
+                    self.codec.set_compression(if threshold >= 0 {
+                        Some(threshold as usize)
+                    } else {
+                        None
+                    });
+                }
+                login::clientbound::LoginPluginRequest::ID => {
+                    // No channels are understood, the same as a vanilla client
+                    let request: login::clientbound::LoginPluginRequest = decode(&packet)?;
+                    self.send(&login::serverbound::LoginPluginResponse {
+                        message_id: request.message_id,
+                        successful: false,
+                        data: Remaining(Bytes::new()),
+                    })?;
+                }
+                login::clientbound::LoginSuccess::ID => {
+                    let success: login::clientbound::LoginSuccess = decode(&packet)?;
+                    let uuid = Uuid::parse_str(&success.uuid)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                    self.state = State::Play;
+                    return Ok(uuid);
+                }
+                id => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unexpected login packet 0x{:02X}", id),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Waits for the server to put the player in the world, confirming the teleport and
+    /// sending settings the way a vanilla client does. Returns where the player was placed.
+    /// Anything sent before that is kept for `recv`.
+    pub fn spawn(&mut self) -> Result<clientbound::PosLook, Error> {
+        self.send(&serverbound::ClientSettings {
+            locale: String::from("en_us"),
+            view_distance: 10,
+            chat_mode: VarInt(0),
+            chat_colors: true,
+            skin_parts: 0x7F,
+            main_hand: VarInt(1),
+        })?;
+
+        let mut brand = Vec::new();
+        codec::write_string(&mut brand, BRAND);
+        self.send(&serverbound::PluginMessage {
+            channel: String::from("minecraft:brand"),
+            data: Remaining(Bytes::from(brand)),
+        })?;
+
+        loop {
+            let packet = self.read_play()?;
+            let position = match self.read::<clientbound::PosLook>(&packet)? {
+                Some(position) => position,
+                None => {
+                    self.pending.push_back(packet);
+                    continue;
+                }
+            };
+
+            self.send(&serverbound::TeleportConfirm {
+                teleport_id: position.teleport_id,
+            })?;
+            self.send(&serverbound::PosLook {
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                yaw: position.yaw,
+                pitch: position.pitch,
+                on_ground: false,
+            })?;
+
+            return Ok(position);
+        }
+    }
+
+    /// The next packet from the server in Play, with the id it has in the client's version.
+    /// Keep alives are answered before they're handed out, and a disconnect is returned as an
+    /// error carrying its reason.
+    pub fn recv(&mut self) -> Result<Packet, Error> {
+        match self.pending.pop_front() {
+            Some(packet) => Ok(packet),
+            None => self.read_play(),
+        }
+    }
+
+    /// Reads `packet` as a `P` if that's what it is. Play packets are matched by their 1.13.2
+    /// id, so packets whose fields changed in the client's version aren't matched and have to
+    /// be decoded with their own declarations.
+    pub fn read<P: ClientboundPacket>(&self, packet: &Packet) -> Result<Option<P>, Error> {
+        let id = match self.state {
+            State::Play => self.version.play_id(true, packet.id),
+            _ => Some(packet.id),
+        };
+
+        if id == Some(P::ID) {
+            Ok(Some(decode(packet)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sends a packet to the server. Play packets are given as they are in 1.13.2 and sent
+    /// with the client's version's id. Ones whose fields changed can only go out through
+    /// `send_packet`.
+    pub fn send<P: ServerboundPacket>(&mut self, packet: &P) -> Result<(), Error> {
+        let id = match self.state {
+            State::Play => self.version.serverbound_play_id(P::ID).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Packet 0x{:02X} changed in {}, send it with send_packet",
+                        P::ID,
+                        self.version.name()
+                    ),
+                )
+            })?,
+            _ => P::ID,
+        };
+
+        let mut data = BytesMut::with_capacity(packet.size());
+        packet.encode(&mut data);
+        self.send_packet(id, data.freeze())
+    }
+
+    /// Sends a packet as is, without mapping its id.
+    pub fn send_packet(&mut self, id: i32, data: Bytes) -> Result<(), Error> {
+        self.write_buf.clear();
+        self.codec
+            .encode(Packet::new(id, data), &mut self.write_buf)?;
+        self.stream.write_all(&self.write_buf)
+    }
+
+    /// Answers an encryption request with a new shared secret, encrypting everything after.
+    fn encrypt(&mut self, request: login::clientbound::EncryptionRequest) -> Result<(), Error> {
+        let key = RsaPublicKey::from_public_key_der(&request.public_key)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let shared_secret: [u8; 16] = rand::random();
+        let encrypt = |data: &[u8]| {
+            key.encrypt(&mut OsRng, Pkcs1v15Encrypt, data)
+                .map_err(|e| Error::other(e.to_string()))
+        };
+
+        self.send(&login::serverbound::EncryptionResponse {
+            shared_secret: encrypt(&shared_secret)?,
+            verify_token: encrypt(&request.verify_token)?,
+        })?;
+        self.codec
+            .set_encryption(encryption::Cipher::new(&shared_secret)?);
+        self.server_hash = Some(encryption::server_hash(
+            &request.server_id,
+            &shared_secret,
+            &request.public_key,
+        ));
+
+        Ok(())
+    }
+
+    fn read_play(&mut self) -> Result<Packet, Error> {
+        let packet = self.read_packet()?;
+
+        if let Some(keep_alive) = self.read::<clientbound::KeepAlive>(&packet)? {
+            self.send(&serverbound::KeepAlive { id: keep_alive.id })?;
+        }
+        if let Some(disconnect) = self.read::<clientbound::Disconnect>(&packet)? {
+            return Err(disconnected(&disconnect.reason));
+        }
+
+        Ok(packet)
+    }
+
+    fn expect<P: ClientboundPacket>(&mut self) -> Result<P, Error> {
+        let packet = self.read_packet()?;
+        match self.read(&packet)? {
+            Some(packet) => Ok(packet),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Expected packet 0x{:02X} but got 0x{:02X}",
+                    P::ID,
+                    packet.id
+                ),
+            )),
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Packet, Error> {
+        let mut buf = [0; READ_SIZE];
+        loop {
+            if let Some(packet) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(packet);
+            }
+
+            let read = self.stream.read(&mut buf)?;
+            if read == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Server closed the connection",
+                ));
+            }
+            self.read_buf.extend_from_slice(&buf[..read]);
+        }
+    }
+}
+
+fn decode<P: ClientboundPacket>(packet: &Packet) -> Result<P, ProtocolError> {
+    P::decode(&mut packet.data.clone().into_buf())
+}
+
+// Disconnect reasons are chat components, which the server only ever gives text
+fn disconnected(reason: &str) -> Error {
+    let text = serde_json::from_str::<Value>(reason)
+        .ok()
+        .and_then(|reason| reason.get("text").and_then(Value::as_str).map(String::from))
+        .unwrap_or_else(|| reason.to_string());
+
+    Error::new(ErrorKind::ConnectionAborted, text)
+}
@@ -1,116 +1,215 @@
 use crate::world;
 use crate::world::chunks::{ChunkColumn, ChunkSection};
-use byteorder::{BigEndian, WriteBytesExt};
-use std::mem::transmute;
+use bytes::{Buf, BufMut};
+use std::fmt;
 use tokio::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+/// The longest string the protocol allows when a field doesn't set its own limit.
+pub const MAX_STRING_LENGTH: usize = 32767;
+
+/// Why data sent by a client couldn't be read.
+#[derive(Debug)]
+pub enum ProtocolError {
+    ShortRead {
+        needed: usize,
+        remaining: usize,
+    },
+    VarIntTooLong,
+    NegativeLength(i32),
+    PacketTooLarge {
+        length: usize,
+        max: usize,
+    },
+    StringTooLong {
+        length: usize,
+        max: usize,
+    },
+    InvalidUtf8,
+    /// Any of the above, in the body of a packet received in the named connection state.
+    InPacket {
+        id: i32,
+        state: &'static str,
+        error: Box<ProtocolError>,
+    },
+}
 
-pub fn encode_bool(val: bool) -> Vec<u8> {
-    if val {
-        vec![1]
-    } else {
-        vec![0]
+impl ProtocolError {
+    /// Records which packet `error` came from, if it came from reading one.
+    pub fn in_packet(error: Error, id: i32, state: &'static str) -> Error {
+        let is_protocol = error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ProtocolError>());
+        if !is_protocol {
+            return error;
+        }
+
+        let inner = error
+            .into_inner()
+            .and_then(|inner| inner.downcast::<ProtocolError>().ok())
+            .expect("Error was checked to be a ProtocolError");
+
+        ProtocolError::InPacket {
+            id,
+            state,
+            error: inner,
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::ShortRead { needed, remaining } => write!(
+                f,
+                "Packet ended early, needed {} bytes but only {} remained",
+                needed, remaining
+            ),
+            ProtocolError::VarIntTooLong => write!(f, "VarInt was too long"),
+            ProtocolError::NegativeLength(length) => write!(f, "Negative length {}", length),
+            ProtocolError::PacketTooLarge { length, max } => write!(
+                f,
+                "Packet was {} bytes long, longer than the limit of {}",
+                length, max
+            ),
+            ProtocolError::StringTooLong { length, max } => write!(
+                f,
+                "String was {} long, longer than the limit of {}",
+                length, max
+            ),
+            ProtocolError::InvalidUtf8 => write!(f, "String had invalid data"),
+            ProtocolError::InPacket { id, state, error } => {
+                write!(f, "Bad {} packet 0x{:02X}: {}", state, id, error)
+            }
+        }
     }
 }
 
-pub fn encode_byte(num: i8) -> Vec<u8> {
-    unsafe {
-        let bytes: [u8; 1] = transmute(num.to_be());
-        bytes.to_vec()
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for Error {
+    fn from(error: ProtocolError) -> Error {
+        Error::new(ErrorKind::InvalidData, error)
     }
 }
 
-pub fn encode_ubyte(num: u8) -> Vec<u8> {
-    vec![num]
+// Writers put their value straight onto the end of the buffer. `Vec<u8>` grows as it's written
+// to but `BytesMut` doesn't, so callers writing into one need to reserve space up front.
+
+pub fn write_bool<B: BufMut>(buf: &mut B, val: bool) {
+    buf.put_u8(val as u8);
 }
 
-pub fn encode_double(num: f64) -> Vec<u8> {
-    let mut val = [0u8; 8];
-    val.as_mut()
-        .write_f64::<BigEndian>(num)
-        .expect("Unable to encode double");
+pub fn write_byte<B: BufMut>(buf: &mut B, num: i8) {
+    buf.put_i8(num);
+}
 
-    val.to_vec()
+pub fn write_ubyte<B: BufMut>(buf: &mut B, num: u8) {
+    buf.put_u8(num);
 }
 
-pub fn encode_float(num: f32) -> Vec<u8> {
-    let mut val = [0u8; 4];
-    val.as_mut()
-        .write_f32::<BigEndian>(num)
-        .expect("Unable to encode float");
+pub fn write_double<B: BufMut>(buf: &mut B, num: f64) {
+    buf.put_f64_be(num);
+}
 
-    val.to_vec()
+pub fn write_float<B: BufMut>(buf: &mut B, num: f32) {
+    buf.put_f32_be(num);
 }
 
-pub fn encode_long(num: i64) -> Vec<u8> {
-    unsafe {
-        let bytes: [u8; 8] = transmute(num.to_be());
-        bytes.to_vec()
-    }
+pub fn write_short<B: BufMut>(buf: &mut B, num: i16) {
+    buf.put_i16_be(num);
 }
 
-pub fn encode_int(num: i32) -> Vec<u8> {
-    unsafe {
-        let bytes: [u8; 4] = transmute(num.to_be());
-        bytes.to_vec()
-    }
+pub fn write_ushort<B: BufMut>(buf: &mut B, num: u16) {
+    buf.put_u16_be(num);
+}
+
+pub fn write_long<B: BufMut>(buf: &mut B, num: i64) {
+    buf.put_i64_be(num);
 }
 
-pub fn encode_varint(mut num: i32) -> Vec<u8> {
-    let mut result = Vec::new();
+pub fn write_int<B: BufMut>(buf: &mut B, num: i32) {
+    buf.put_i32_be(num);
+}
+
+pub fn write_varint<B: BufMut>(buf: &mut B, num: i32) {
+    // Shifted as unsigned so negative numbers still end after five bytes
+    let mut num = num as u32;
 
     loop {
-        let mut value = num & 0b01111111;
+        let value = (num & 0b01111111) as u8;
         num >>= 7;
         if num == 0 {
-            result.push(value as u8);
+            buf.put_u8(value);
             break;
         } else {
-            value |= 0b10000000;
-            result.push(value as u8);
+            buf.put_u8(value | 0b10000000);
         }
     }
+}
 
-    result
+/// The number of bytes `write_varint` takes to write `num`.
+pub fn varint_size(num: i32) -> usize {
+    let mut num = num as u32;
+    let mut size = 1;
+
+    while num >= 0b10000000 {
+        num >>= 7;
+        size += 1;
+    }
+
+    size
 }
 
-pub fn encode_position(vector: &world::Vector) -> Vec<u8> {
+pub fn write_position<B: BufMut>(buf: &mut B, vector: &world::Vector) {
     let value: i64 = ((vector.x as i64 & 0x3FFFFFF) << 38)
         | ((vector.y as i64 & 0xFFF) << 26)
         | (vector.z as i64 & 0x3FFFFFF);
 
-    encode_long(value)
+    write_long(buf, value);
 }
 
-pub fn encode_string(string: &str) -> Vec<u8> {
-    let mut encoded = Vec::new();
-    let bytes = string.as_bytes();
-    encoded.extend_from_slice(&encode_varint(bytes.len() as i32));
-    encoded.extend_from_slice(bytes);
+/// Positions as packed from 1.14 on, with y moved to the low bits.
+pub fn write_position_1_14<B: BufMut>(buf: &mut B, vector: &world::Vector) {
+    let value: i64 = ((vector.x as i64 & 0x3FFFFFF) << 38)
+        | ((vector.z as i64 & 0x3FFFFFF) << 12)
+        | (vector.y as i64 & 0xFFF);
 
-    encoded
+    write_long(buf, value);
 }
 
-pub fn encode_chunk_column(column: &ChunkColumn) {
-    let mut data = Vec::new();
-    data.append(&mut encode_int(column.x));
-    data.append(&mut encode_int(column.z));
-    data.append(&mut encode_bool(true)); // Full chunk
+pub fn write_string<B: BufMut>(buf: &mut B, string: &str) {
+    write_byte_array(buf, string.as_bytes());
+}
+
+pub fn write_uuid<B: BufMut>(buf: &mut B, uuid: &Uuid) {
+    buf.put_slice(uuid.as_bytes());
+}
+
+pub fn write_byte_array<B: BufMut>(buf: &mut B, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as i32);
+    buf.put_slice(bytes);
+}
+
+pub fn write_chunk_column<B: BufMut>(buf: &mut B, column: &ChunkColumn) {
+    write_int(buf, column.x);
+    write_int(buf, column.z);
+    write_bool(buf, true); // Full chunk
 
     let mut mask: u8 = 0;
     for section in &column.sections {
         mask >>= 1;
-        match section {
-            Some(_) => mask |= 0b10000000,
-            None => (),
+        if section.is_some() {
+            mask |= 0b10000000;
         }
     }
-    data.append(&mut encode_ubyte(mask));
+    write_ubyte(buf, mask);
 }
 
-pub fn encode_chunk_section(section: &ChunkSection) {
-    let mut data = Vec::new();
-    data.append(&mut encode_ubyte(14)); // Bits per block
-                                        //Empty palette for direct usage
+pub fn write_chunk_section<B: BufMut>(buf: &mut B, _section: &ChunkSection) {
+    write_ubyte(buf, 14); // Bits per block
+                          //Empty palette for direct usage
 }
 
 pub fn encode_ids(ids: Vec<u32>, size: i32) -> Vec<u64> {
@@ -137,62 +236,153 @@ pub fn encode_ids(ids: Vec<u32>, size: i32) -> Vec<u64> {
     data
 }
 
-pub fn read_long(bytes: &mut Vec<u8>) -> i64 {
-    let mut num_bytes = [0u8; 8];
-    let mut byte_slice = bytes.drain(..8).into_iter();
-    for i in 0..8 {
-        num_bytes[i] = byte_slice.next().unwrap();
-    }
+pub fn read_bool<B: Buf>(buf: &mut B) -> Result<bool, ProtocolError> {
+    Ok(read_ubyte(buf)? != 0)
+}
 
-    i64::from_be_bytes(num_bytes)
+pub fn read_byte<B: Buf>(buf: &mut B) -> Result<i8, ProtocolError> {
+    ensure(buf, 1)?;
+    Ok(buf.get_i8())
 }
 
-pub fn read_ushort(bytes: &mut Vec<u8>) -> u16 {
-    let mut num_bytes = [0u8; 2];
-    let mut byte_slice = bytes.drain(..2).into_iter();
-    for i in 0..2 {
-        num_bytes[i] = byte_slice.next().unwrap();
-    }
+pub fn read_ubyte<B: Buf>(buf: &mut B) -> Result<u8, ProtocolError> {
+    ensure(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+pub fn read_short<B: Buf>(buf: &mut B) -> Result<i16, ProtocolError> {
+    ensure(buf, 2)?;
+    Ok(buf.get_i16_be())
+}
+
+pub fn read_int<B: Buf>(buf: &mut B) -> Result<i32, ProtocolError> {
+    ensure(buf, 4)?;
+    Ok(buf.get_i32_be())
+}
+
+pub fn read_float<B: Buf>(buf: &mut B) -> Result<f32, ProtocolError> {
+    ensure(buf, 4)?;
+    Ok(buf.get_f32_be())
+}
+
+pub fn read_double<B: Buf>(buf: &mut B) -> Result<f64, ProtocolError> {
+    ensure(buf, 8)?;
+    Ok(buf.get_f64_be())
+}
+
+pub fn read_long<B: Buf>(buf: &mut B) -> Result<i64, ProtocolError> {
+    ensure(buf, 8)?;
+    Ok(buf.get_i64_be())
+}
+
+pub fn read_ushort<B: Buf>(buf: &mut B) -> Result<u16, ProtocolError> {
+    ensure(buf, 2)?;
+    Ok(buf.get_u16_be())
+}
+
+pub fn read_position<B: Buf>(buf: &mut B) -> Result<world::Vector, ProtocolError> {
+    let value = read_long(buf)?;
+
+    // Each part is shifted to the top first so shifting it back down extends the sign
+    Ok(world::Vector {
+        x: (value >> 38) as f64,
+        y: ((value << 26) >> 52) as f64,
+        z: ((value << 38) >> 38) as f64,
+    })
+}
+
+pub fn read_position_1_14<B: Buf>(buf: &mut B) -> Result<world::Vector, ProtocolError> {
+    let value = read_long(buf)?;
 
-    u16::from_be_bytes(num_bytes)
+    Ok(world::Vector {
+        x: (value >> 38) as f64,
+        y: ((value << 52) >> 52) as f64,
+        z: ((value << 26) >> 38) as f64,
+    })
 }
 
-pub fn read_varint(bytes: &mut Vec<u8>) -> Result<i32, Error> {
+pub fn read_varint<B: Buf>(buf: &mut B) -> Result<i32, ProtocolError> {
     let mut result: i32 = 0;
     let mut bytes_read = 0;
 
-    for byte in bytes.iter() {
-        let value = (*byte & 0b01111111) as i32;
+    loop {
+        let byte = read_ubyte(buf)?;
+        let value = (byte & 0b01111111) as i32;
         result |= value << (7 * bytes_read);
         bytes_read += 1;
 
-        if bytes_read > 5 {
-            return Err(Error::new(ErrorKind::InvalidData, "VarInt was too long"));
+        if byte & 0b10000000 == 0 {
+            return Ok(result);
         }
 
-        if *byte & 0b10000000 == 0 {
-            break;
+        if bytes_read == 5 {
+            return Err(ProtocolError::VarIntTooLong);
         }
     }
+}
+
+/// Reads a string of at most `max_length` characters. Anything longer is rejected before its
+/// bytes are copied out of the buffer.
+pub fn read_string<B: Buf>(buf: &mut B, max_length: usize) -> Result<String, ProtocolError> {
+    let length = read_length(buf)?;
+    // Each character takes at most four bytes in UTF-8
+    if length > max_length * 4 {
+        return Err(ProtocolError::StringTooLong {
+            length,
+            max: max_length,
+        });
+    }
+
+    let string =
+        String::from_utf8(read_bytes(buf, length)?).map_err(|_| ProtocolError::InvalidUtf8)?;
+
+    // The limit counts UTF-16 code units, same as the vanilla server
+    let length = string.encode_utf16().count();
+    if length > max_length {
+        return Err(ProtocolError::StringTooLong {
+            length,
+            max: max_length,
+        });
+    }
+
+    Ok(string)
+}
+
+pub fn read_byte_array<B: Buf>(buf: &mut B) -> Result<Vec<u8>, ProtocolError> {
+    let length = read_length(buf)?;
+    read_bytes(buf, length)
+}
 
-    bytes.drain(..bytes_read);
+pub fn read_uuid<B: Buf>(buf: &mut B) -> Result<Uuid, ProtocolError> {
+    ensure(buf, 16)?;
+    let mut uuid_bytes = [0u8; 16];
+    buf.copy_to_slice(&mut uuid_bytes);
 
-    Ok(result)
+    Ok(Uuid::from_bytes(uuid_bytes))
 }
 
-pub fn read_string(bytes: &mut Vec<u8>) -> Result<String, Error> {
-    let length = read_varint(bytes)? as usize;
-    let drained_bytes = bytes.drain(..length);
-    let mut char_vec = Vec::new();
-    for b in drained_bytes {
-        char_vec.push(b);
+fn read_length<B: Buf>(buf: &mut B) -> Result<usize, ProtocolError> {
+    let length = read_varint(buf)?;
+    if length < 0 {
+        return Err(ProtocolError::NegativeLength(length));
     }
 
-    match String::from_utf8(char_vec) {
-        Ok(s) => Ok(s),
-        Err(_) => Err(Error::new(
-            ErrorKind::InvalidData,
-            "String had invalid data",
-        )),
+    Ok(length as usize)
+}
+
+fn read_bytes<B: Buf>(buf: &mut B, length: usize) -> Result<Vec<u8>, ProtocolError> {
+    ensure(buf, length)?;
+    let mut bytes = vec![0; length];
+    buf.copy_to_slice(&mut bytes);
+
+    Ok(bytes)
+}
+
+fn ensure<B: Buf>(buf: &B, needed: usize) -> Result<(), ProtocolError> {
+    let remaining = buf.remaining();
+    if remaining < needed {
+        Err(ProtocolError::ShortRead { needed, remaining })
+    } else {
+        Ok(())
     }
 }
@@ -0,0 +1,154 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use aes::Aes128;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::rand_core::OsRng;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use sha1::{Digest, Sha1};
+use std::io::{Error, ErrorKind};
+
+pub struct ServerKey {
+    private_key: RsaPrivateKey,
+    public_der: Vec<u8>,
+}
+
+impl ServerKey {
+    pub fn generate() -> Result<ServerKey, Error> {
+        let private_key =
+            RsaPrivateKey::new(&mut OsRng, 1024).map_err(|e| Error::other(e.to_string()))?;
+        let public_der = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .map_err(|e| Error::other(e.to_string()))?
+            .into_vec();
+
+        Ok(ServerKey {
+            private_key,
+            public_der,
+        })
+    }
+
+    pub fn public_der(&self) -> &[u8] {
+        &self.public_der
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.private_key
+            .decrypt(Pkcs1v15Encrypt, data)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Unable to decrypt client data"))
+    }
+}
+
+/// AES/CFB8 stream cipher keyed with the shared secret, which doubles as the IV.
+pub struct Cipher {
+    encryptor: cfb8::Encryptor<Aes128>,
+    decryptor: cfb8::Decryptor<Aes128>,
+}
+
+impl Cipher {
+    pub fn new(shared_secret: &[u8]) -> Result<Cipher, Error> {
+        let invalid = |_| Error::new(ErrorKind::InvalidData, "Shared secret must be 16 bytes");
+
+        Ok(Cipher {
+            encryptor: cfb8::Encryptor::new_from_slices(shared_secret, shared_secret)
+                .map_err(invalid)?,
+            decryptor: cfb8::Decryptor::new_from_slices(shared_secret, shared_secret)
+                .map_err(invalid)?,
+        })
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.encryptor
+                .encrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+        }
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.decryptor
+                .decrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+        }
+    }
+}
+
+/// Minecraft's server hash: a SHA-1 digest printed as a signed two's complement hex number.
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_der: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_der);
+    let mut digest = hasher.finalize().to_vec();
+
+    let negative = digest[0] & 0b10000000 != 0;
+    if negative {
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (value, overflow) = byte.overflowing_add(1);
+                *byte = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let hex = hex.trim_start_matches('0');
+
+    if negative {
+        format!("-{}", hex)
+    } else {
+        hex.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_hashes_match_known_digests() {
+        // The usual examples, with the name standing in for everything that gets hashed
+        assert_eq!(
+            server_hash("Notch", &[], &[]),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+        assert_eq!(
+            server_hash("jeb_", &[], &[]),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+        assert_eq!(
+            server_hash("simon", &[], &[]),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+
+    #[test]
+    fn server_hashes_cover_the_secret_and_key() {
+        assert_eq!(
+            server_hash("", b"Not", b"ch"),
+            server_hash("Notch", &[], &[])
+        );
+    }
+
+    #[test]
+    fn ciphers_round_trip_a_byte_at_a_time() {
+        let secret = *b"0123456789abcdef";
+        let mut server = Cipher::new(&secret).unwrap();
+        let mut client = Cipher::new(&secret).unwrap();
+
+        let mut data = b"Encrypted in CFB8".to_vec();
+        client.encrypt(&mut data[..5]);
+        client.encrypt(&mut data[5..]);
+        assert_ne!(&data[..], b"Encrypted in CFB8");
+
+        server.decrypt(&mut data);
+        assert_eq!(&data[..], b"Encrypted in CFB8");
+    }
+
+    #[test]
+    fn shared_secrets_have_to_be_16_bytes() {
+        assert!(Cipher::new(&[0; 15]).is_err());
+        assert!(Cipher::new(&[0; 32]).is_err());
+    }
+}
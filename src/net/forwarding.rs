@@ -0,0 +1,244 @@
+use super::codec;
+use crate::config::Forwarding;
+use crate::entities::player::ProfileProperty;
+use bytes::{Buf, Bytes, IntoBuf};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+/// The forwarding version requested from Velocity. Version 1 is understood by every release.
+pub const VELOCITY_VERSION: u8 = 1;
+const VELOCITY_SIGNATURE_LENGTH: usize = 32;
+
+/// Which kind of proxy forwards player info, leaving out Velocity's secret so connections can
+/// keep it to themselves.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Proxy {
+    BungeeCord,
+    Velocity,
+}
+
+impl Proxy {
+    pub fn from_config(forwarding: &Forwarding) -> Option<Proxy> {
+        match forwarding {
+            Forwarding::None => None,
+            Forwarding::BungeeCord => Some(Proxy::BungeeCord),
+            Forwarding::Velocity { .. } => Some(Proxy::Velocity),
+        }
+    }
+}
+
+/// Player info a proxy forwarded on behalf of the client.
+pub struct Forwarded {
+    pub address: IpAddr,
+    pub uuid: Uuid,
+    /// Only sent by Velocity, BungeeCord leaves it to the client's Login Start.
+    pub username: Option<String>,
+    pub properties: Vec<ProfileProperty>,
+}
+
+/// Splits BungeeCord's forwarded fields off a handshake's server address, returning the real
+/// host and the forwarded info. Fields are separated by null characters: host, client IP,
+/// undashed UUID, then optionally the profile properties as JSON.
+pub fn read_bungeecord(server_address: &str) -> Result<(String, Forwarded), Error> {
+    let fields: Vec<&str> = server_address.split('\0').collect();
+    if fields.len() < 3 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!",
+        ));
+    }
+
+    let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+    let address = fields[1]
+        .parse()
+        .map_err(|_| invalid("Invalid forwarded address"))?;
+    let uuid = Uuid::parse_str(fields[2]).map_err(|_| invalid("Invalid forwarded UUID"))?;
+    let properties = match fields.get(3) {
+        Some(json) => serde_json::from_str(json)?,
+        None => Vec::new(),
+    };
+
+    let forwarded = Forwarded {
+        address,
+        uuid,
+        username: None,
+        properties,
+    };
+
+    Ok((fields[0].to_string(), forwarded))
+}
+
+/// Checks the signature on Velocity's response to the player info request and reads the info
+/// from it.
+pub fn read_velocity(secret: &str, data: Bytes) -> Result<Forwarded, Error> {
+    if data.len() < VELOCITY_SIGNATURE_LENGTH {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Velocity player info was too short",
+        ));
+    }
+
+    let (signature, info) = data.split_at(VELOCITY_SIGNATURE_LENGTH);
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid Velocity secret"))?;
+    mac.update(info);
+    mac.verify_slice(signature).map_err(|_| {
+        Error::new(
+            ErrorKind::PermissionDenied,
+            "Unable to verify player details",
+        )
+    })?;
+
+    let mut buf = data.slice_from(VELOCITY_SIGNATURE_LENGTH).into_buf();
+    let version = codec::read_varint(&mut buf)?;
+    if version < i32::from(VELOCITY_VERSION) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported Velocity forwarding version {}", version),
+        ));
+    }
+
+    let address = codec::read_string(&mut buf, codec::MAX_STRING_LENGTH)?
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid forwarded address"))?;
+    let uuid = codec::read_uuid(&mut buf)?;
+    let username = codec::read_string(&mut buf, 16)?;
+    let properties = read_properties(&mut buf)?;
+
+    Ok(Forwarded {
+        address,
+        uuid,
+        username: Some(username),
+        properties,
+    })
+}
+
+fn read_properties<B: Buf>(buf: &mut B) -> Result<Vec<ProfileProperty>, codec::ProtocolError> {
+    let count = codec::read_varint(buf)?;
+    let mut properties = Vec::new();
+
+    for _ in 0..count {
+        let name = codec::read_string(buf, codec::MAX_STRING_LENGTH)?;
+        let value = codec::read_string(buf, codec::MAX_STRING_LENGTH)?;
+        let signature = if codec::read_bool(buf)? {
+            Some(codec::read_string(buf, codec::MAX_STRING_LENGTH)?)
+        } else {
+            None
+        };
+
+        properties.push(ProfileProperty {
+            name,
+            value,
+            signature,
+        });
+    }
+
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+    fn velocity_info(version: i32) -> Vec<u8> {
+        let mut info = Vec::new();
+        codec::write_varint(&mut info, version);
+        codec::write_string(&mut info, "203.0.113.7");
+        codec::write_uuid(&mut info, &Uuid::parse_str(UUID).unwrap());
+        codec::write_string(&mut info, "Notch");
+        codec::write_varint(&mut info, 1);
+        codec::write_string(&mut info, "textures");
+        codec::write_string(&mut info, "e30=");
+        codec::write_bool(&mut info, true);
+        codec::write_string(&mut info, "c2lnbmF0dXJl");
+        info
+    }
+
+    fn sign(secret: &str, info: &[u8]) -> Bytes {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(info);
+
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend_from_slice(info);
+        Bytes::from(data)
+    }
+
+    #[test]
+    fn bungeecord_fields_are_split_off_the_address() {
+        let address = format!(
+            "play.example.com\u{0}203.0.113.7\u{0}{}\u{0}{}",
+            UUID.replace('-', ""),
+            r#"[{"name":"textures","value":"e30=","signature":"c2ln"}]"#
+        );
+        let (host, forwarded) = read_bungeecord(&address).unwrap();
+
+        assert_eq!(host, "play.example.com");
+        assert_eq!(forwarded.address, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded.uuid, Uuid::parse_str(UUID).unwrap());
+        assert_eq!(forwarded.username, None);
+        assert_eq!(forwarded.properties.len(), 1);
+        assert_eq!(forwarded.properties[0].signature.as_deref(), Some("c2ln"));
+    }
+
+    #[test]
+    fn bungeecord_properties_are_optional() {
+        let address = format!("localhost\u{0}::1\u{0}{}", UUID.replace('-', ""));
+        let (_, forwarded) = read_bungeecord(&address).unwrap();
+
+        assert_eq!(forwarded.address, "::1".parse::<IpAddr>().unwrap());
+        assert!(forwarded.properties.is_empty());
+    }
+
+    #[test]
+    fn bungeecord_without_forwarding_is_refused() {
+        let error = read_bungeecord("localhost").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+
+        let address = format!("localhost\u{0}nowhere\u{0}{}", UUID);
+        assert!(read_bungeecord(&address).is_err());
+        assert!(read_bungeecord("localhost\u{0}::1\u{0}not-a-uuid").is_err());
+    }
+
+    #[test]
+    fn signed_velocity_info_is_read() {
+        let forwarded = read_velocity("secret", sign("secret", &velocity_info(1))).unwrap();
+
+        assert_eq!(forwarded.address, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded.uuid, Uuid::parse_str(UUID).unwrap());
+        assert_eq!(forwarded.username.as_deref(), Some("Notch"));
+        assert_eq!(forwarded.properties.len(), 1);
+        assert_eq!(forwarded.properties[0].name, "textures");
+        assert_eq!(
+            forwarded.properties[0].signature.as_deref(),
+            Some("c2lnbmF0dXJl")
+        );
+    }
+
+    #[test]
+    fn velocity_info_with_the_wrong_signature_is_refused() {
+        let error = read_velocity("secret", sign("guess", &velocity_info(1)))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+
+        // Tampering with the info after it was signed
+        let mut data = sign("secret", &velocity_info(1)).to_vec();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let error = read_velocity("secret", Bytes::from(data)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn malformed_velocity_info_is_refused() {
+        assert!(read_velocity("secret", Bytes::from(&[0; 16][..])).is_err());
+        assert!(read_velocity("secret", sign("secret", &velocity_info(0))).is_err());
+        assert!(read_velocity("secret", sign("secret", &velocity_info(1)[..10])).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+// Entry points for the fuzz targets in fuzz/, which the tests below also replay inputs
+// through. Each takes bytes as a client would send them and has to return without panicking,
+// whatever they are.
+
+use crate::config::{Config, Forwarding};
+use crate::net::codec;
+use crate::net::packets::{handshake, login, play, play_1_14, status};
+use crate::net::session::{GameProfile, SessionVerifier, Verification};
+use crate::net::{encryption, shutdown, throttle, NetState, PacketCodec, PlayerSocket, Settings};
+use crate::Obelisk;
+use bytes::{Bytes, BytesMut, IntoBuf};
+use std::cell::RefCell;
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock, RwLock};
+use tokio::codec::Decoder;
+use tokio::io::{AsyncRead, AsyncWrite, Error};
+use tokio::prelude::*;
+use tokio::runtime::current_thread::Runtime;
+use uuid::Uuid;
+
+/// Lets connections run over `Client` as well as TCP streams.
+pub trait Stream: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Stream for T {}
+
+const SHARED_SECRET: &[u8; 16] = b"obelisk fuzzing!";
+
+/// Reads one kind of field from the front of `data`, with the first byte choosing which.
+pub fn read_field(data: &[u8]) {
+    let (kind, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let buf = &mut Bytes::from(data).into_buf();
+
+    let _ = match kind % 16 {
+        0 => codec::read_bool(buf).map(drop),
+        1 => codec::read_byte(buf).map(drop),
+        2 => codec::read_ubyte(buf).map(drop),
+        3 => codec::read_short(buf).map(drop),
+        4 => codec::read_ushort(buf).map(drop),
+        5 => codec::read_int(buf).map(drop),
+        6 => codec::read_long(buf).map(drop),
+        7 => codec::read_float(buf).map(drop),
+        8 => codec::read_double(buf).map(drop),
+        9 => codec::read_varint(buf).map(drop),
+        10 => codec::read_position(buf).map(drop),
+        11 => codec::read_position_1_14(buf).map(drop),
+        12 => codec::read_string(buf, codec::MAX_STRING_LENGTH).map(drop),
+        13 => codec::read_string(buf, 16).map(drop),
+        14 => codec::read_byte_array(buf).map(drop),
+        _ => codec::read_uuid(buf).map(drop),
+    };
+}
+
+/// Decodes `data` as packets, the way a connection's codec would. Every packet declaration
+/// gets a go at reading each frame, as any of them could be what the id picks.
+pub fn decode_frames(data: &[u8]) {
+    let (settings, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let mut codec = PacketCodec::new();
+    let state = match settings & 0x03 {
+        0 => NetState::Handshake,
+        1 => NetState::Status,
+        2 => NetState::Login,
+        _ => NetState::Play(Uuid::nil()),
+    };
+    codec.set_max_packet_size(state.max_packet_size());
+
+    // Only a codec that has read the handshake can be in another state, and only logins turn
+    // on compression and encryption
+    codec.awaiting_handshake = matches!(state, NetState::Handshake);
+    if matches!(state, NetState::Login | NetState::Play(_)) {
+        if settings & 0x04 != 0 {
+            codec.set_compression(Some(64));
+        }
+        if settings & 0x08 != 0 {
+            codec.set_encryption(encryption::Cipher::new(SHARED_SECRET).unwrap());
+        }
+    }
+
+    // Arrives in two parts, so frames split across reads are covered
+    let split = data.len() / 2;
+    let mut src = BytesMut::from(&data[..split]);
+    for part in &[&data[split..], &[]] {
+        loop {
+            match codec.decode(&mut src) {
+                Ok(Some(packet)) => describe(packet.id, &packet.data),
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+        src.extend_from_slice(part);
+    }
+}
+
+fn describe(id: i32, data: &Bytes) {
+    let describers: &[fn(i32, &mut Cursor<Bytes>) -> _] = &[
+        handshake::serverbound::describe,
+        status::serverbound::describe,
+        login::serverbound::describe,
+        play::serverbound::describe,
+        play_1_14::serverbound::describe,
+    ];
+
+    for describe in describers {
+        let _ = describe(id, &mut data.clone().into_buf());
+    }
+}
+
+/// Runs a connection that receives `data` and then hangs up, through handshake, status, login
+/// and into play as far as the data gets it. The first byte picks how the server is set up.
+pub fn connection(data: &[u8]) {
+    let (settings, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let config = Config {
+        online_mode: settings & 0x01 != 0,
+        compression_threshold: if settings & 0x02 != 0 { Some(64) } else { None },
+        forwarding: match settings >> 2 & 0x03 {
+            1 => Forwarding::BungeeCord,
+            2 => Forwarding::Velocity {
+                secret: String::from("secret"),
+            },
+            _ => Forwarding::None,
+        },
+        ..Config::for_tests()
+    };
+
+    let limiter = Arc::new(throttle::Limiter::new(&config));
+    let server = Obelisk::new(config, Arc::new(AnyoneJoined));
+    let settings = Settings::new(&server, key());
+
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 25565);
+    let connection = limiter.connect(address.ip()).unwrap();
+    let drain = shutdown::Drain::default();
+    let socket = PlayerSocket::new(
+        Arc::new(RwLock::new(server)),
+        &settings,
+        connection,
+        drain.track(),
+        Box::new(Client(Cursor::new(data.to_vec()))),
+        address,
+        BytesMut::new(),
+    );
+
+    RUNTIME.with(|runtime| {
+        let _ = runtime.borrow_mut().block_on(socket);
+    });
+}
+
+thread_local! {
+    // Has the timer keep alives need, and is far too slow to make for every input
+    static RUNTIME: RefCell<Runtime> = RefCell::new(Runtime::new().unwrap());
+}
+
+// Generating a key takes longer than running most inputs, so there's only ever one
+fn key() -> Arc<encryption::ServerKey> {
+    static KEY: OnceLock<Arc<encryption::ServerKey>> = OnceLock::new();
+    KEY.get_or_init(|| Arc::new(encryption::ServerKey::generate().unwrap()))
+        .clone()
+}
+
+/// Lets every online mode login through, so fuzzing doesn't need a session server.
+struct AnyoneJoined;
+
+impl SessionVerifier for AnyoneJoined {
+    fn has_joined(&self, username: &str, _server_hash: &str) -> Verification {
+        Box::new(future::ok(Some(GameProfile {
+            id: Uuid::new_v5(&Uuid::NAMESPACE_DNS, username.as_bytes()),
+            name: username.to_string(),
+            properties: Vec::new(),
+        })))
+    }
+}
+
+/// A client that has already sent everything it's going to. What the server writes back is
+/// thrown away.
+struct Client(Cursor<Vec<u8>>);
+
+impl Read for Client {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.read(buf)
+    }
+}
+
+impl AsyncRead for Client {}
+
+impl Write for Client {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Client {
+    fn shutdown(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::Path;
+
+    // Minimized inputs the fuzzers have crashed on, kept in fuzz/regressions/<target>, and the
+    // hand-written seeds in fuzz/seeds/<target> that reach far in, so every test run replays
+    // them rather than only fuzzing
+    fn replay(target: &str, run: fn(&[u8])) {
+        let fuzz = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+
+        let mut inputs = 0;
+        for dir in &[fuzz.join("regressions"), fuzz.join("seeds")] {
+            // Not every target has crashed yet
+            let entries = match fs::read_dir(dir.join(target)) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => panic!("Unable to read {}: {}", dir.display(), e),
+            };
+
+            for entry in entries {
+                let path = entry.unwrap().path();
+                println!("Replaying {}", path.display());
+                run(&fs::read(&path).unwrap());
+                inputs += 1;
+            }
+        }
+
+        assert!(inputs > 0, "No inputs for {}", target);
+    }
+
+    #[test]
+    fn read_field() {
+        replay("read_field", super::read_field);
+    }
+
+    #[test]
+    fn decode_frames() {
+        replay("decode_frames", super::decode_frames);
+    }
+
+    #[test]
+    fn connection() {
+        replay("connection", super::connection);
+    }
+}
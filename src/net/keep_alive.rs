@@ -0,0 +1,141 @@
+use futures::{Async, Future};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+// Vanilla clients give up on a server they haven't heard from in 20 seconds
+const INTERVAL: Duration = Duration::from_secs(15);
+// How long the client has to answer, the same as vanilla gives it
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct KeepAlive {
+    // When the next keep alive is due, or the pending one runs out of time
+    deadline: Delay,
+    pending: Option<(i64, Instant)>,
+}
+
+impl KeepAlive {
+    pub fn new() -> KeepAlive {
+        KeepAlive {
+            deadline: Delay::new(Instant::now() + INTERVAL),
+            pending: None,
+        }
+    }
+
+    /// Resolves to the id of the next keep alive once one is due. Fails if the client hasn't
+    /// answered the previous one in time.
+    pub fn poll(&mut self) -> Result<Async<i64>, Error> {
+        match self.deadline.poll().map_err(Error::other)? {
+            Async::Ready(()) => (),
+            Async::NotReady => return Ok(Async::NotReady),
+        }
+
+        if let Some((_, sent)) = self.pending {
+            if sent.elapsed() >= TIMEOUT {
+                return Err(Error::new(ErrorKind::TimedOut, "Timed out"));
+            }
+
+            // Only the time since it was sent counts, however late the deadline was noticed
+            self.deadline.reset(sent + TIMEOUT);
+            return self.poll();
+        }
+
+        let id = rand::random();
+        let sent = Instant::now();
+        self.pending = Some((id, sent));
+        self.deadline.reset(sent + TIMEOUT);
+        Ok(Async::Ready(id))
+    }
+
+    /// Validates a keep alive from the client, returning the round trip time.
+    pub fn receive(&mut self, id: i64) -> Result<Duration, Error> {
+        match self.pending {
+            Some((pending, sent)) if pending == id => {
+                self.pending = None;
+                self.deadline.reset(sent + INTERVAL);
+                Ok(sent.elapsed())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "Invalid keep alive")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+
+    // Polls on a runtime, since the deadline needs its timer
+    fn poll(keep_alive: &mut KeepAlive) -> Result<Async<i64>, Error> {
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(|| Ok::<_, ()>(keep_alive.poll())))
+            .unwrap()
+    }
+
+    // A keep alive sent `ago`, with its deadline passed
+    fn sent(ago: Duration) -> KeepAlive {
+        let sent = Instant::now() - ago;
+        KeepAlive {
+            deadline: Delay::new(sent),
+            pending: Some((7, sent)),
+        }
+    }
+
+    #[test]
+    fn answers_with_the_pending_id_are_accepted() {
+        let mut keep_alive = sent(Duration::from_millis(50));
+
+        let round_trip = keep_alive.receive(7).unwrap();
+        assert!(round_trip >= Duration::from_millis(50));
+        assert!(keep_alive.pending.is_none());
+    }
+
+    #[test]
+    fn answers_with_another_id_are_rejected() {
+        let mut keep_alive = sent(Duration::from_millis(50));
+
+        let error = keep_alive.receive(8).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(keep_alive.pending.is_some());
+    }
+
+    #[test]
+    fn answers_with_nothing_pending_are_rejected() {
+        let mut keep_alive = KeepAlive::new();
+
+        let error = keep_alive.receive(7).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn clients_that_take_too_long_to_answer_time_out() {
+        let mut keep_alive = sent(TIMEOUT);
+
+        let error = poll(&mut keep_alive).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn a_late_deadline_doesnt_time_out_a_keep_alive_just_sent() {
+        let mut keep_alive = sent(Duration::from_millis(50));
+
+        assert!(poll(&mut keep_alive).unwrap().is_not_ready());
+        assert!(keep_alive.pending.is_some());
+    }
+
+    #[test]
+    fn keep_alives_are_sent_once_due() {
+        let mut keep_alive = KeepAlive {
+            deadline: Delay::new(Instant::now()),
+            pending: None,
+        };
+
+        let id = match poll(&mut keep_alive).unwrap() {
+            Async::Ready(id) => id,
+            Async::NotReady => panic!("No keep alive was sent"),
+        };
+        assert!(keep_alive.receive(id).is_ok());
+    }
+}
@@ -1,54 +1,246 @@
-use super::codec;
+use super::encryption;
+use super::forwarding;
+use super::packets::login::{clientbound, serverbound};
+use super::packets::{Remaining, ServerboundPacket, VarInt};
+use super::session::{GameProfile, Verification};
 use super::{Packet, PlayerSocket};
+use crate::config::Forwarding;
+use bytes::Bytes;
+use futures::{Async, Future};
 use serde_json::json;
 use std::io::{Error, ErrorKind};
 use uuid::Uuid;
 
+pub enum LoginState {
+    Start,
+    EncryptionRequested {
+        username: String,
+        verify_token: [u8; 4],
+    },
+    Authenticating(Verification),
+    VelocityRequested {
+        message_id: i32,
+    },
+}
+
 pub fn handle_login(socket: &mut PlayerSocket, packet: &mut Packet) -> Result<Option<Uuid>, Error> {
-    if packet.id == 0 {
-        // Take in username and generate uuid, then use a central command to create a player
-        // and return some sort of reference
-        let username = read_login_start(socket, packet)?;
-        println!("Received connection from {}", username);
-        let uuid = Uuid::new_v5(&Uuid::NAMESPACE_DNS, username.as_bytes());
-
-        let player = socket
-            .server
-            .write()
-            .unwrap()
-            .create_player(uuid.clone(), username.clone());
-        send_login_success(socket, &uuid, &username);
-
-        Ok(Some(uuid))
-    } else {
-        Err(Error::new(
+    match (packet.id, &socket.login_state) {
+        (serverbound::LoginStart::ID, LoginState::Start) => {
+            let username = packet.read::<serverbound::LoginStart>()?.username;
+            println!("Received connection from {} ({})", username, socket.address);
+
+            let (online_mode, velocity) = {
+                let server = socket.server.read().unwrap();
+                let velocity = match server.config.forwarding {
+                    Forwarding::Velocity { .. } => true,
+                    Forwarding::None | Forwarding::BungeeCord => false,
+                };
+                (server.config.online_mode, velocity)
+            };
+
+            if let Some(forwarded) = socket.forwarded.take() {
+                let profile = GameProfile {
+                    id: forwarded.uuid,
+                    name: username,
+                    properties: forwarded.properties,
+                };
+
+                finish_login(socket, profile).map(Some)
+            } else if velocity {
+                let message_id = rand::random();
+                send_velocity_request(socket, message_id);
+                socket.login_state = LoginState::VelocityRequested { message_id };
+
+                Ok(None)
+            } else if online_mode {
+                let verify_token: [u8; 4] = rand::random();
+                send_encryption_request(socket, &verify_token);
+                socket.login_state = LoginState::EncryptionRequested {
+                    username,
+                    verify_token,
+                };
+
+                Ok(None)
+            } else {
+                let uuid = Uuid::new_v5(&Uuid::NAMESPACE_DNS, username.as_bytes());
+                let profile = GameProfile {
+                    id: uuid,
+                    name: username,
+                    properties: Vec::new(),
+                };
+
+                finish_login(socket, profile).map(Some)
+            }
+        }
+        (serverbound::EncryptionResponse::ID, LoginState::EncryptionRequested { .. }) => {
+            read_encryption_response(socket, packet.read()?)?;
+            Ok(None)
+        }
+        (serverbound::LoginPluginResponse::ID, LoginState::VelocityRequested { message_id }) => {
+            let message_id = *message_id;
+            read_velocity_response(socket, message_id, packet.read()?).map(Some)
+        }
+        _ => Err(Error::new(
             ErrorKind::InvalidData,
             "Unsupported login packet id",
-        ))
+        )),
     }
 }
 
-fn read_login_start(socket: &mut PlayerSocket, packet: &mut Packet) -> Result<String, Error> {
-    codec::read_string(&mut packet.data)
+/// Drives an in-flight session server check, finishing the login once it succeeds.
+pub fn poll_authentication(socket: &mut PlayerSocket) -> Result<Option<Uuid>, Error> {
+    let profile = match socket.login_state {
+        LoginState::Authenticating(ref mut verification) => match verification.poll() {
+            Ok(Async::Ready(Some(profile))) => profile,
+            Ok(Async::Ready(None)) => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Failed to verify username!",
+                ))
+            }
+            Ok(Async::NotReady) => return Ok(None),
+            Err(e) => {
+                // What went wrong is for the log, not the player
+                println!("Session server check for {} failed: {}", socket.address, e);
+                return Err(Error::other(
+                    "Authentication servers are down. Please try again later, sorry!",
+                ));
+            }
+        },
+        _ => return Ok(None),
+    };
+
+    finish_login(socket, profile).map(Some)
 }
 
-fn send_login_success(socket: &mut PlayerSocket, uuid: &Uuid, username: &str) {
-    let mut data = Vec::new();
-    data.append(&mut codec::encode_string(&uuid.to_hyphenated().to_string()));
-    data.append(&mut codec::encode_string(username));
+fn finish_login(socket: &mut PlayerSocket, profile: GameProfile) -> Result<Uuid, Error> {
+    let compression_threshold = socket.server.read().unwrap().config.compression_threshold;
+    if let Some(threshold) = compression_threshold {
+        socket.set_compression(threshold);
+    }
+
+    // Only created once nothing else can fail, so a failed login never leaves a player behind
+    {
+        let mut server = socket.server.write().unwrap();
+        if server.players.contains_key(&profile.id) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "You are already logged in to this server",
+            ));
+        }
 
-    socket.send_packet(0x2, data)
+        server.create_player(
+            profile.id,
+            profile.name.clone(),
+            socket.address,
+            profile.properties,
+            socket.output.stats(),
+        );
+    }
+
+    send_login_success(socket, &profile.id, &profile.name);
+
+    Ok(profile.id)
 }
 
-fn send_login_disconnect(socket: &mut PlayerSocket) {
-    let mut data = Vec::new();
+fn read_encryption_response(
+    socket: &mut PlayerSocket,
+    response: serverbound::EncryptionResponse,
+) -> Result<(), Error> {
+    let (username, expected_token) = match &socket.login_state {
+        LoginState::EncryptionRequested {
+            username,
+            verify_token,
+        } => (username.clone(), *verify_token),
+        _ => unreachable!(),
+    };
+
+    let shared_secret = socket.key.decrypt(&response.shared_secret)?;
+    if socket.key.decrypt(&response.verify_token)? != expected_token {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Verify token did not match",
+        ));
+    }
 
+    socket.set_encryption(&shared_secret)?;
+
+    let server_hash = encryption::server_hash("", &shared_secret, socket.key.public_der());
+    let verifier = socket.server.read().unwrap().session_verifier.clone();
+    socket.login_state = LoginState::Authenticating(verifier.has_joined(&username, &server_hash));
+
+    Ok(())
+}
+
+fn read_velocity_response(
+    socket: &mut PlayerSocket,
+    message_id: i32,
+    response: serverbound::LoginPluginResponse,
+) -> Result<Uuid, Error> {
+    if response.message_id.0 != message_id {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Unexpected login plugin response",
+        ));
+    }
+
+    if !response.successful {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "This server requires you to connect with Velocity.",
+        ));
+    }
+
+    let secret = match socket.server.read().unwrap().config.forwarding {
+        Forwarding::Velocity { ref secret } => secret.clone(),
+        Forwarding::None | Forwarding::BungeeCord => unreachable!(),
+    };
+
+    let forwarded = forwarding::read_velocity(&secret, response.data.0)?;
+    socket.set_forwarded_address(&forwarded);
+    if !socket.allow_login() {
+        return Err(Error::new(ErrorKind::PermissionDenied, super::THROTTLED));
+    }
+
+    let profile = GameProfile {
+        id: forwarded.uuid,
+        name: forwarded.username.unwrap_or_default(),
+        properties: forwarded.properties,
+    };
+
+    finish_login(socket, profile)
+}
+
+fn send_velocity_request(socket: &mut PlayerSocket, message_id: i32) {
+    socket.send(clientbound::LoginPluginRequest {
+        message_id: VarInt(message_id),
+        channel: String::from(forwarding::VELOCITY_CHANNEL),
+        data: Remaining(Bytes::from(vec![forwarding::VELOCITY_VERSION])),
+    })
+}
+
+fn send_encryption_request(socket: &mut PlayerSocket, verify_token: &[u8]) {
+    let public_key = socket.key.public_der().to_vec();
+
+    socket.send(clientbound::EncryptionRequest {
+        server_id: String::new(), // Empty since 1.7
+        public_key,
+        verify_token: verify_token.to_vec(),
+    })
+}
+
+fn send_login_success(socket: &mut PlayerSocket, uuid: &Uuid, username: &str) {
+    socket.send(clientbound::LoginSuccess {
+        uuid: uuid.to_hyphenated().to_string(),
+        username: username.to_string(),
+    })
+}
+
+pub fn disconnect(reason: &str) -> clientbound::Disconnect {
     let chat = json!({
-        "text": "Login failed"
+        "text": reason
     })
     .to_string();
 
-    data.append(&mut codec::encode_string(&chat));
-
-    socket.send_packet(0x0, data)
+    clientbound::Disconnect { reason: chat }
 }
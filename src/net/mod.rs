@@ -1,61 +1,509 @@
 use crate::Obelisk;
-use bytes::{BufMut, BytesMut};
-use std::collections::VecDeque;
-use std::net::SocketAddr;
+use bytes::{BufMut, Bytes, BytesMut, IntoBuf};
+use codec::ProtocolError;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use net2::TcpBuilder;
+use packets::{handshake, login as login_packets, ClientboundPacket, ServerboundPacket, VarInt};
+use std::collections::{HashSet, VecDeque};
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process;
 use std::sync::{Arc, RwLock};
-use tokio::codec::{Decoder, Encoder, Framed};
+use std::time::{Duration, Instant};
+use tokio::codec::{Decoder, Encoder, Framed, FramedParts};
 use tokio::io::{Error, ErrorKind};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::prelude::AsyncSink::{NotReady, Ready};
 use tokio::prelude::*;
+use tokio::reactor::Handle;
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
 use uuid::Uuid;
+use version::Version;
 
+pub mod capture;
+pub mod channels;
+pub mod client;
 pub mod codec;
+mod encryption;
+mod forwarding;
+#[cfg(any(test, feature = "fuzzing"))]
+#[doc(hidden)]
+pub mod fuzz;
+mod keep_alive;
 mod login;
+pub mod output;
+pub mod packets;
 mod play;
-mod status;
+mod proxy;
+mod query;
+mod rcon;
+pub mod session;
+pub mod shutdown;
+pub mod status;
+mod throttle;
+pub mod version;
+
+// Legacy (pre-1.7) server list pings start with this byte instead of a length prefix
+const LEGACY_PING: u8 = 0xFE;
+// What follows 0xFE in a 1.6 ping: 0x01, then a plugin message (0xFA) on "MC|PingHost"
+const LEGACY_PING_HOST: u8 = 0xFA;
+// How long a connection that has only sent 0xFE, or 0xFE 0x01, has to send more before it's
+// answered as a legacy ping from before 1.6
+const LEGACY_PING_WAIT: Duration = Duration::from_millis(500);
+// Only there to stop a broken server running a client out of memory, so it's generous
+const MAX_CLIENTBOUND_SIZE: usize = 8 * 1024 * 1024;
+// Vanilla closes connections it hasn't heard from in this long. Keep alives take over in Play
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// Given to clients that log in again too soon after the last login from their address
+const THROTTLED: &str = "Connection throttled! Please wait before reconnecting.";
+// Vanilla's 600 ticks, which waiting for the session server counts towards
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct Packet {
     id: i32,
-    data: Vec<u8>,
+    data: Bytes,
 }
 
 impl Packet {
-    pub fn new(id: i32, data: Vec<u8>) -> Packet {
+    pub fn new(id: i32, data: Bytes) -> Packet {
         Packet { id, data }
     }
+
+    pub fn encode<P: ClientboundPacket>(packet: &P) -> Packet {
+        let mut data = BytesMut::with_capacity(packet.size());
+        packet.encode(&mut data);
+        Packet::new(P::ID, data.freeze())
+    }
+
+    pub fn read<P: ServerboundPacket>(&mut self) -> Result<P, ProtocolError> {
+        read_front(&mut self.data, P::decode)
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
 }
 
-pub fn start(server: Arc<RwLock<Obelisk>>) {
-    let address = SocketAddr::new("127.0.0.1".parse().unwrap(), 25565);
-    let listener = TcpListener::bind(&address).expect("Unable to bind TcpListener");
+/// Runs `read` over the front of `bytes` and drops whatever it consumed, without copying.
+fn read_front<T, F>(bytes: &mut Bytes, read: F) -> Result<T, ProtocolError>
+where
+    F: FnOnce(&mut Cursor<Bytes>) -> Result<T, ProtocolError>,
+{
+    // Cloning only bumps the reference count on the underlying buffer
+    let mut buf = bytes.clone().into_buf();
+    let value = read(&mut buf)?;
+    let consumed = buf.position() as usize;
 
-    let network_loop = listener
-        .incoming()
-        .for_each(move |socket| {
-            let framed = Framed::new(socket, PacketCodec::new());
-            tokio::spawn(
-                PlayerSocket {
-                    server: server.clone(),
-                    stream: framed,
-                    state: NetState::Handshake,
-                    output: VecDeque::new(),
-                }
-                .map_err(|e| println!("connection error: {:?}", e)),
-            );
+    bytes.advance(consumed);
+    Ok(value)
+}
+
+/// What every new connection needs from the server, read once at startup so accepting one and
+/// answering its server list ping never waits on the server's lock.
+struct Settings {
+    key: Arc<encryption::ServerKey>,
+    proxy_protocol: bool,
+    trusted_proxies: Vec<IpAddr>,
+    proxy: Option<forwarding::Proxy>,
+    max_queued_output: usize,
+    capture_dir: Option<PathBuf>,
+    shutdown: shutdown::Shutdown,
+    status: Arc<status::Cache>,
+}
+
+impl Settings {
+    fn new(server: &Obelisk, key: Arc<encryption::ServerKey>) -> Settings {
+        let config = &server.config;
+        Settings {
+            key,
+            proxy_protocol: config.proxy_protocol,
+            trusted_proxies: config.trusted_proxies.clone(),
+            proxy: forwarding::Proxy::from_config(&config.forwarding),
+            max_queued_output: config.max_queued_output,
+            capture_dir: config.capture_dir.clone(),
+            shutdown: server.shutdown.clone(),
+            status: server.status.clone(),
+        }
+    }
+}
+
+/// Binds every configured listen address, then serves connections on all of them until the
+/// server is stopped and its clients have been disconnected.
+pub fn start(server: Arc<RwLock<Obelisk>>) -> Result<(), Error> {
+    let addresses = server.read().unwrap().config.listen.clone();
+    if addresses.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "No addresses to listen on",
+        ));
+    }
+
+    let listeners = addresses
+        .iter()
+        .map(bind)
+        .collect::<Result<Vec<_>, Error>>()?;
+    let query = match server.read().unwrap().config.query {
+        Some(address) => Some((query::bind(&address)?, address)),
+        None => None,
+    };
+    let rcon = match server.read().unwrap().config.rcon {
+        Some(ref rcon) if rcon.password.is_empty() => {
+            return Err(Error::new(ErrorKind::InvalidInput, "RCON needs a password"))
+        }
+        Some(ref rcon) => Some((bind(&rcon.address)?, rcon.address, rcon.password.clone())),
+        None => None,
+    };
+    let key = Arc::new(encryption::ServerKey::generate()?);
+    let settings = Arc::new(Settings::new(&server.read().unwrap(), key));
+    let limiter = Arc::new(throttle::Limiter::new(&server.read().unwrap().config));
+    let (shutdown, timeout) = {
+        let server = server.read().unwrap();
+        (server.shutdown.clone(), server.config.shutdown_timeout)
+    };
+    let drain = shutdown::Drain::default();
+    let running = drain.track();
+
+    let mut runtime = Runtime::new()?;
+    let network_loop = {
+        let server = server.clone();
+        let shutdown = shutdown.clone();
+        future::lazy(move || {
+            for (listener, address) in listeners.into_iter().zip(addresses) {
+                println!("Listening on {}", address);
+
+                let server = server.clone();
+                let settings = settings.clone();
+                let limiter = limiter.clone();
+                let running = running.clone();
+                tokio::spawn(
+                    shutdown.until(
+                        listener
+                            .incoming()
+                            .for_each(move |socket| {
+                                accept(&server, &settings, &limiter, &running, socket);
+                                Ok(())
+                            })
+                            .map_err(move |e| println!("accept error on {}: {:?}", address, e)),
+                    ),
+                );
+            }
+
+            if let Some((socket, address)) = query {
+                println!("Answering queries on {}", address);
+                tokio::spawn(shutdown.until(query::serve(&server.read().unwrap(), socket)));
+            }
+
+            if let Some((listener, address, password)) = rcon {
+                println!("RCON listening on {}", address);
+                let server = server.clone();
+                tokio::spawn(
+                    shutdown.until(
+                        listener
+                            .incoming()
+                            .for_each(move |socket| {
+                                rcon::accept(server.clone(), password.clone(), socket);
+                                Ok(())
+                            })
+                            .map_err(move |e| {
+                                println!("RCON accept error on {}: {:?}", address, e)
+                            }),
+                    ),
+                );
+            }
+
+            tokio::spawn(stop_on_signal(shutdown));
+            Ok(())
+        })
+    };
+
+    runtime.spawn(network_loop);
+    let _ = runtime.block_on(shutdown.wait());
+
+    // Players are disconnected as their sockets notice the shutdown
+    if runtime.block_on(drain.wait().timeout(timeout)).is_err() {
+        println!(
+            "Closing connections that are still sending after {} seconds",
+            timeout.as_secs()
+        );
+    }
+
+    // Dropping whatever's left closes it and saves its player
+    let _ = runtime.shutdown_now().wait();
+
+    let mut server = server.write().unwrap();
+    let uuids: Vec<Uuid> = server.players.keys().cloned().collect();
+    for uuid in uuids {
+        server.remove_player(&uuid);
+    }
+
+    Ok(())
+}
+
+/// Stops the server on the first SIGINT or SIGTERM. A second one gives up on stopping cleanly.
+fn stop_on_signal(shutdown: shutdown::Shutdown) -> impl Future<Item = (), Error = ()> {
+    signals()
+        .for_each(move |_| {
+            if shutdown.stop() {
+                println!("Stopping the server");
+            } else {
+                println!("Stopping the server now");
+                process::exit(1);
+            }
 
             Ok(())
         })
-        .map_err(|e| println!("accept error: {:?}", e));
+        .map_err(|e| println!("Unable to listen for signals: {}", e))
+}
 
-    tokio::run(network_loop);
+#[cfg(unix)]
+fn signals() -> impl Stream<Item = (), Error = Error> {
+    use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+    Signal::new(SIGINT)
+        .flatten_stream()
+        .select(Signal::new(SIGTERM).flatten_stream())
+        .map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn signals() -> impl Stream<Item = (), Error = Error> {
+    tokio_signal::ctrl_c().flatten_stream()
+}
+
+fn bind(address: &SocketAddr) -> Result<TcpListener, Error> {
+    let builder = match address {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            // Otherwise an IPv6 wildcard also takes the IPv4 port and the two can't be listed
+            // side by side
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            builder
+        }
+    };
+
+    let listener = builder
+        .reuse_address(true)?
+        .bind(address)
+        .and_then(|builder| builder.listen(1024))
+        .map_err(|e| Error::new(e.kind(), format!("Unable to bind {}: {}", address, e)))?;
+
+    TcpListener::from_std(listener, &Handle::default())
+}
+
+fn accept(
+    server: &Arc<RwLock<Obelisk>>,
+    settings: &Arc<Settings>,
+    limiter: &Arc<throttle::Limiter>,
+    running: &shutdown::Running,
+    socket: TcpStream,
+) {
+    let peer = match socket.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            println!("connection error: {:?}", e);
+            return;
+        }
+    };
+
+    let proxy_protocol = settings.proxy_protocol;
+    let trusted = settings.trusted_proxies.contains(&peer.ip());
+
+    let server = server.clone();
+    let settings = settings.clone();
+    let limiter = limiter.clone();
+    let running = running.clone();
+
+    // Limits apply to the client's real address, so with a proxy they wait for its header
+    let open = move |socket: TcpStream, address: SocketAddr, read_buf| {
+        #[cfg(any(test, feature = "fuzzing"))]
+        let socket = Box::new(socket);
+
+        match limiter.connect(address.ip()) {
+            Some(connection) => Some(PlayerSocket::new(
+                server, &settings, connection, running, socket, address, read_buf,
+            )),
+            None => {
+                println!("Refused connection from {}, too many connections", address);
+                None
+            }
+        }
+    };
+
+    if !proxy_protocol {
+        if let Some(player_socket) = open(socket, peer, BytesMut::new()) {
+            tokio::spawn(
+                player_socket.map_err(move |e| println!("connection error from {}: {:?}", peer, e)),
+            );
+        }
+    } else if trusted {
+        tokio::spawn(
+            proxy::read_header(socket)
+                .timeout(READ_TIMEOUT)
+                .map_err(|e| match e.into_inner() {
+                    Some(e) => e,
+                    None => Error::new(ErrorKind::TimedOut, "No PROXY header in time"),
+                })
+                .and_then(move |(socket, source, read_buf)| {
+                    match open(socket, source.unwrap_or(peer), read_buf) {
+                        Some(player_socket) => future::Either::A(player_socket),
+                        None => future::Either::B(future::ok(())),
+                    }
+                })
+                .map_err(move |e| println!("connection error via {}: {:?}", peer, e)),
+        );
+    } else {
+        // Dropping the socket closes it
+        println!("Refused connection from untrusted proxy {}", peer);
+    }
 }
 
-struct PacketCodec;
+struct PacketCodec {
+    compression_threshold: Option<usize>,
+    // Largest packet the client may send in the connection's current state
+    max_packet_size: usize,
+    cipher: Option<encryption::Cipher>,
+    // The cipher decrypts as soon as it's set but only encrypts once this is on too
+    encrypting: bool,
+    // Number of bytes at the start of the read buffer which have already been decrypted
+    decrypted: usize,
+    awaiting_handshake: bool,
+    legacy: bool,
+    // What followed 0xFE, while the connection could still be either a legacy ping or a frame
+    undecided_ping: Option<Bytes>,
+    // Packet bodies are encoded into this before they're queued, see `packets::encode_into`
+    bodies: BytesMut,
+    // Kept between packets so compressing one doesn't allocate
+    compressed: Vec<u8>,
+}
 
 impl PacketCodec {
     fn new() -> PacketCodec {
-        PacketCodec {}
+        PacketCodec {
+            compression_threshold: None,
+            max_packet_size: NetState::Handshake.max_packet_size(),
+            cipher: None,
+            encrypting: false,
+            decrypted: 0,
+            awaiting_handshake: true,
+            legacy: false,
+            undecided_ping: None,
+            bodies: BytesMut::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    /// A codec for the other end of the connection, reading what a server sends.
+    fn client() -> PacketCodec {
+        PacketCodec {
+            compression_threshold: None,
+            max_packet_size: MAX_CLIENTBOUND_SIZE,
+            cipher: None,
+            encrypting: false,
+            decrypted: 0,
+            awaiting_handshake: false,
+            legacy: false,
+            undecided_ping: None,
+            bodies: BytesMut::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    fn set_compression(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
+    }
+
+    /// Turns on AES/CFB8 in both directions.
+    fn set_encryption(&mut self, cipher: encryption::Cipher) {
+        self.start_decrypting(cipher);
+        self.start_encrypting();
+    }
+
+    fn start_decrypting(&mut self, cipher: encryption::Cipher) {
+        self.cipher = Some(cipher);
+        self.decrypted = 0;
+    }
+
+    fn start_encrypting(&mut self) {
+        self.encrypting = true;
+    }
+
+    fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.max_packet_size = max_packet_size;
+    }
+
+    /// Takes a connection that stopped after 0xFE or 0xFE 0x01 as a legacy ping from before
+    /// 1.6, which is all those send. Nothing if what was read so far has already been decided.
+    fn take_undecided_ping(&mut self) -> Option<Packet> {
+        let data = self.undecided_ping.take()?;
+        self.awaiting_handshake = false;
+        self.legacy = true;
+        Some(Packet::new(LEGACY_PING as i32, data))
+    }
+
+    fn encode_packet<P: ClientboundPacket>(&mut self, packet: &P) -> Packet {
+        Packet::new(P::ID, packets::encode_into(&mut self.bodies, packet))
+    }
+
+    /// Checks a length the client declared for a packet, before anything is allocated for it.
+    fn check_length(&self, length: i32) -> Result<usize, ProtocolError> {
+        if length < 0 {
+            return Err(ProtocolError::NegativeLength(length));
+        }
+
+        let length = length as usize;
+        if length > self.max_packet_size {
+            return Err(ProtocolError::PacketTooLarge {
+                length,
+                max: self.max_packet_size,
+            });
+        }
+
+        Ok(length)
+    }
+
+    fn decompress(&self, mut bytes: Bytes) -> Result<Bytes, Error> {
+        let threshold = match self.compression_threshold {
+            Some(threshold) => threshold,
+            None => return Ok(bytes),
+        };
+
+        let data_length = read_front(&mut bytes, codec::read_varint)?;
+        if data_length == 0 {
+            // Packet was below the threshold and sent uncompressed
+            return Ok(bytes);
+        }
+
+        let data_length = self.check_length(data_length)?;
+        if data_length < threshold {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Compressed packet was below the compression threshold",
+            ));
+        }
+
+        // Reading one byte past the declared length is enough to tell it was wrong
+        let mut decompressed = Vec::with_capacity(data_length);
+        ZlibDecoder::new(&bytes[..])
+            .take(data_length as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+
+        if decompressed.len() != data_length {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Decompressed packet length did not match data length",
+            ));
+        }
+
+        Ok(Bytes::from(decompressed))
     }
 }
 
@@ -64,39 +512,72 @@ impl Decoder for PacketCodec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut varint_size = 0;
-        let mut finished = false;
-        for b in src.iter() {
-            varint_size += 1;
-            if b & 0b10000000 == 0 {
-                finished = true;
-                break;
-            }
+        if let Some(cipher) = &mut self.cipher {
+            cipher.decrypt(&mut src[self.decrypted..]);
+            self.decrypted = src.len();
+        }
 
-            if varint_size == 5 {
-                return Err(Error::new(ErrorKind::InvalidData, "Varint too long!"));
+        if self.legacy {
+            // Nothing else is expected from a legacy ping once it's been answered
+            src.clear();
+            return Ok(None);
+        }
+
+        if self.awaiting_handshake {
+            self.undecided_ping = None;
+            match is_legacy_ping(src) {
+                Some(true) => {
+                    self.awaiting_handshake = false;
+                    self.legacy = true;
+                    src.advance(1);
+                    let data = src.take().freeze();
+                    return Ok(Some(Packet::new(LEGACY_PING as i32, data)));
+                }
+                Some(false) => self.awaiting_handshake = false,
+                None if src.is_empty() => return Ok(None),
+                None => {
+                    self.undecided_ping = Some(Bytes::from(&src[1..]));
+                    return Ok(None);
+                }
             }
         }
 
-        if !finished {
+        // Nothing is taken out of the buffer until the whole frame is there
+        let (length, length_size) = {
+            let mut buf = Cursor::new(&src[..]);
+            match codec::read_varint(&mut buf) {
+                Ok(length) => (length, buf.position() as usize),
+                Err(ProtocolError::ShortRead { .. }) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let length = self.check_length(length)?;
+        if src.len() < length_size + length {
+            src.reserve(length_size + length - src.len());
             return Ok(None);
         }
 
-        let len = codec::read_varint(&mut src.split_to(varint_size).to_vec())?;
-        let mut bytes = src.split_to(len as usize).to_vec();
-        let bytes_missing = len - bytes.len() as i32;
+        src.advance(length_size);
+        let bytes = src.split_to(length).freeze();
+        self.decrypted = src.len();
 
-        if bytes_missing == 0 {
-            let id = codec::read_varint(&mut bytes)?;
-            Ok(Some(Packet { id, data: bytes }))
-        } else if bytes_missing > 0 {
-            Ok(None)
-        } else {
-            Err(Error::new(
-                ErrorKind::InvalidData,
-                "Data longer than packet length",
-            ))
-        }
+        let mut data = self.decompress(bytes)?;
+        let id = read_front(&mut data, codec::read_varint)?;
+        Ok(Some(Packet { id, data }))
+    }
+}
+
+/// Whether a connection's first bytes are a legacy ping rather than a frame, or `None` if it
+/// can't be told yet. 0xFE also starts the length of any frame 254 bytes long (or 382, 510...),
+/// and a 254 byte frame goes on with 0x01 just like a 1.4 ping, so only the plugin message a 1.6
+/// ping sends next settles it. Older pings stop before that and are only answered once the
+/// client has gone quiet, see `PacketCodec::take_undecided_ping`.
+fn is_legacy_ping(src: &[u8]) -> Option<bool> {
+    match src {
+        [] | [LEGACY_PING] | [LEGACY_PING, 0x01] => None,
+        [LEGACY_PING, 0x01, LEGACY_PING_HOST, ..] => Some(true),
+        _ => Some(false),
     }
 }
 
@@ -105,15 +586,56 @@ impl Encoder for PacketCodec {
     type Error = Error;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let id = codec::encode_varint(item.id);
-        let data = item.data;
-        let length = id.len() + data.len();
-        dst.reserve(length);
+        if self.legacy {
+            // Legacy responses aren't length prefixed, the id is written as a single byte
+            dst.reserve(1 + item.data.len());
+            dst.put_u8(item.id as u8);
+            dst.put_slice(&item.data);
+            return Ok(());
+        }
+
+        let data_length = codec::varint_size(item.id) + item.data.len();
+        let start = dst.len();
+
+        match self.compression_threshold {
+            Some(threshold) if data_length >= threshold => {
+                // Small enough to be kept inline rather than allocated
+                let mut id = BytesMut::with_capacity(5);
+                codec::write_varint(&mut id, item.id);
 
-        let length = codec::encode_varint(length as i32);
-        dst.put_slice(&length);
-        dst.put_slice(&id);
-        dst.put_slice(&data);
+                self.compressed.clear();
+                let mut encoder = ZlibEncoder::new(&mut self.compressed, Compression::default());
+                encoder.write_all(&id)?;
+                encoder.write_all(&item.data)?;
+                encoder.finish()?;
+
+                let length = codec::varint_size(data_length as i32) + self.compressed.len();
+                dst.reserve(codec::varint_size(length as i32) + length);
+                codec::write_varint(dst, length as i32);
+                codec::write_varint(dst, data_length as i32);
+                dst.put_slice(&self.compressed);
+            }
+            Some(_) => {
+                // Data length of 0 marks the packet as uncompressed
+                let length = 1 + data_length;
+                dst.reserve(codec::varint_size(length as i32) + length);
+                codec::write_varint(dst, length as i32);
+                dst.put_u8(0);
+                codec::write_varint(dst, item.id);
+                dst.put_slice(&item.data);
+            }
+            None => {
+                dst.reserve(codec::varint_size(data_length as i32) + data_length);
+                codec::write_varint(dst, data_length as i32);
+                codec::write_varint(dst, item.id);
+                dst.put_slice(&item.data);
+            }
+        }
+
+        match self.cipher {
+            Some(ref mut cipher) if self.encrypting => cipher.encrypt(&mut dst[start..]),
+            _ => (),
+        }
 
         Ok(())
     }
@@ -125,11 +647,92 @@ enum NetState {
     Play(Uuid),
 }
 
+impl NetState {
+    /// Largest packet a client may send in this state, uncompressed.
+    fn max_packet_size(&self) -> usize {
+        match self {
+            NetState::Status => 64,
+            // BungeeCord forwarding puts the player's profile in the handshake and Velocity's
+            // comes back in a login plugin response
+            NetState::Handshake | NetState::Login => 32 * 1024,
+            // Anything a three byte VarInt can describe, the same as vanilla
+            NetState::Play(_) => (1 << 21) - 1,
+        }
+    }
+
+    fn capture_state(&self) -> capture::State {
+        match self {
+            NetState::Handshake => capture::State::Handshake,
+            NetState::Status => capture::State::Status,
+            NetState::Login => capture::State::Login,
+            NetState::Play(_) => capture::State::Play,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            NetState::Handshake => "Handshake",
+            NetState::Status => "Status",
+            NetState::Login => "Login",
+            NetState::Play(_) => "Play",
+        }
+    }
+}
+
+/// A change to how packets are written, made once everything queued before it has been handed
+/// to the codec.
+enum CodecChange {
+    Compression(usize),
+    Encryption,
+}
+
+/// What a connection is read from and written to. Fuzzing, and the tests replaying what it
+/// found, run connections from memory too.
+#[cfg(not(any(test, feature = "fuzzing")))]
+type Transport = TcpStream;
+#[cfg(any(test, feature = "fuzzing"))]
+type Transport = Box<dyn fuzz::Stream>;
+
 pub struct PlayerSocket {
     server: Arc<RwLock<Obelisk>>,
-    stream: Framed<TcpStream, PacketCodec>,
+    stream: Framed<Transport, PacketCodec>,
+    // Where the client connected from, as given by the proxy if there is one
+    address: SocketAddr,
+    connection: throttle::Connection,
     state: NetState,
-    output: VecDeque<Packet>,
+    // Chosen at handshake, the version the client's Play packets are converted to and from
+    version: Version,
+    login_state: login::LoginState,
+    key: Arc<encryption::ServerKey>,
+    keep_alive: Option<keep_alive::KeepAlive>,
+    // Reset by every packet until Play, closing the connection if the client goes quiet
+    read_timeout: Option<Delay>,
+    // Started when the client starts logging in, closing the connection if it hasn't finished
+    login_timeout: Option<Delay>,
+    // Started when the connection's first bytes could be a legacy ping or a frame, answering it
+    // as a ping if nothing more comes
+    legacy_ping_timeout: Option<Delay>,
+    // Id of the last Player Position And Look sent, until the client confirms it
+    pending_teleport: Option<i32>,
+    // The proxy player info is forwarded by, if any
+    proxy: Option<forwarding::Proxy>,
+    // Player info BungeeCord forwarded in the handshake, used once the client logs in
+    forwarded: Option<forwarding::Forwarded>,
+    output: output::OutputQueue,
+    // Codec changes in the order they were made, each with how many packets queued between it
+    // and the one before still have to be handed to the codec first
+    codec_changes: VecDeque<(usize, CodecChange)>,
+    closing: bool,
+    // Set once the connection is given up on, for falling behind or timing out, closing it after
+    // one last flush
+    abandoned: bool,
+    capture: Option<capture::Capture>,
+    // Plugin channels the client registered, the only custom channels it gets sent messages on
+    channels: HashSet<String>,
+    status: Arc<status::Cache>,
+    shutdown: shutdown::Shutdown,
+    // Held until the connection closes, so stopping the server can wait for it
+    _running: shutdown::Running,
 }
 
 impl Future for PlayerSocket {
@@ -137,28 +740,61 @@ impl Future for PlayerSocket {
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        if !self.closing && self.shutdown.poll_stopping() {
+            let message = self.server.read().unwrap().config.shutdown_message.clone();
+            self.disconnect(&message);
+        }
+
         // Check if a packet is available to read
         let mut none = false;
-        loop {
-            match self.stream.poll()? {
+        while !self.closing {
+            let poll = match self.stream.poll() {
+                Ok(poll) => poll,
+                Err(e) => match self.state {
+                    NetState::Login => {
+                        self.login_failed(e);
+                        break;
+                    }
+                    _ => return Err(e),
+                },
+            };
+
+            match poll {
                 Async::Ready(Some(mut packet)) => {
+                    if let Some(ref mut timeout) = self.read_timeout {
+                        timeout.reset(Instant::now() + READ_TIMEOUT);
+                    }
+                    self.capture(capture::Direction::Inbound, &packet);
+                    let id = packet.id;
+                    let state = self.state.name();
+                    let in_packet = |e| ProtocolError::in_packet(e, id, state);
+
                     match self.state {
+                        NetState::Handshake if self.stream.codec().legacy => {
+                            self.answer_legacy_ping(&packet);
+                        }
                         NetState::Handshake => {
-                            self.read_handshake(&mut packet)?;
+                            self.read_handshake(&mut packet).map_err(in_packet)?;
                         }
                         NetState::Status => {
-                            status::handle_status(self, &packet)?;
+                            status::handle_status(self, &mut packet).map_err(in_packet)?;
                         }
-                        NetState::Login => {
-                            match login::handle_login(self, &mut packet)? {
-                                Some(uuid) => {
-                                    play::spawn(self, &uuid)?;
-                                    self.state = NetState::Play(uuid);
-                                }
-                                None => (),
+                        NetState::Login => match login::handle_login(self, &mut packet) {
+                            Ok(Some(uuid)) => self.enter_play(uuid)?,
+                            Ok(None) => (),
+                            Err(e) => self.login_failed(in_packet(e)),
+                        },
+                        NetState::Play(uuid) => {
+                            let result = match self.version.read_play(packet) {
+                                Ok(Some(mut packet)) => play::handle_play(self, &uuid, &mut packet),
+                                Ok(None) => Ok(()),
+                                Err(e) => Err(e.into()),
                             };
+
+                            if let Err(e) = result {
+                                self.disconnect(&in_packet(e).to_string());
+                            }
                         }
-                        NetState::Play(uuid) => {}
                     };
                 }
                 Async::Ready(None) => {
@@ -169,19 +805,36 @@ impl Future for PlayerSocket {
             }
         }
 
-        while let Some(packet) = self.output.pop_front() {
-            match self.stream.start_send(packet)? {
-                NotReady(packet) => {
-                    self.output.push_front(packet);
-                    break;
-                }
-                Ready => (),
+        // Polled after reading so a session check started by this batch of packets gets woken
+        if let (NetState::Login, false) = (&self.state, self.closing) {
+            match login::poll_authentication(self) {
+                Ok(Some(uuid)) => self.enter_play(uuid)?,
+                Ok(None) => (),
+                Err(e) => self.login_failed(e),
             }
         }
 
+        if let (NetState::Handshake, false) = (&self.state, self.closing) {
+            self.poll_undecided_ping()?;
+        }
+
+        if let NetState::Play(_) = self.state {
+            self.poll_keep_alive();
+        } else if !self.closing {
+            self.poll_timeouts()?;
+        }
+
+        self.flush_output()?;
+
+        if self.abandoned {
+            // Whatever of the disconnect fits in the socket goes out, the rest is given up on
+            let _ = self.stream.poll_complete();
+            return Ok(Async::Ready(()));
+        }
+
         futures::try_ready!(self.stream.poll_complete());
 
-        if none {
+        if none || (self.closing && self.output.is_empty()) {
             Ok(Async::Ready(()))
         } else {
             Ok(Async::NotReady)
@@ -190,47 +843,417 @@ impl Future for PlayerSocket {
 }
 
 impl PlayerSocket {
-    pub fn send_packet(&mut self, id: i32, data: Vec<u8>) {
-        self.output.push_back(Packet::new(id, data));
+    /// Wraps a new connection. `read_buf` holds anything already read from the socket, such as
+    /// data that arrived with a PROXY header.
+    fn new(
+        server: Arc<RwLock<Obelisk>>,
+        settings: &Settings,
+        connection: throttle::Connection,
+        running: shutdown::Running,
+        socket: Transport,
+        address: SocketAddr,
+        read_buf: BytesMut,
+    ) -> PlayerSocket {
+        let capture = settings.capture_dir.as_ref().and_then(|dir| {
+            capture::Capture::create(dir, &address)
+                .map_err(|e| println!("Unable to capture packets for {}: {}", address, e))
+                .ok()
+        });
+
+        let mut parts = FramedParts::new(socket, PacketCodec::new());
+        parts.read_buf = read_buf;
+
+        PlayerSocket {
+            server,
+            stream: Framed::from_parts(parts),
+            address,
+            connection,
+            state: NetState::Handshake,
+            version: Version::LATEST,
+            login_state: login::LoginState::Start,
+            key: settings.key.clone(),
+            keep_alive: None,
+            read_timeout: Some(Delay::new(Instant::now() + READ_TIMEOUT)),
+            login_timeout: None,
+            legacy_ping_timeout: None,
+            pending_teleport: None,
+            proxy: settings.proxy,
+            forwarded: None,
+            output: output::OutputQueue::new(settings.max_queued_output),
+            codec_changes: VecDeque::new(),
+            closing: false,
+            abandoned: false,
+            capture,
+            channels: HashSet::new(),
+            status: settings.status.clone(),
+            shutdown: settings.shutdown.clone(),
+            _running: running,
+        }
+    }
+
+    pub fn send<P: ClientboundPacket>(&mut self, packet: P) {
+        let packet = self.stream.codec_mut().encode_packet(&packet);
+        self.queue(packet);
+    }
+
+    pub fn send_packet(&mut self, id: i32, data: Bytes) {
+        self.queue(Packet::new(id, data));
+    }
+
+    fn queue(&mut self, packet: Packet) {
+        if self.abandoned {
+            return;
+        }
+
+        if let Some(packet) = self.outgoing(packet) {
+            if self.output.push_back(packet).is_err() {
+                self.client_too_slow();
+            }
+        }
+    }
+
+    /// Converts a packet for the client's version and captures it, or `None` if the version
+    /// has nothing to convert it to.
+    fn outgoing(&mut self, packet: Packet) -> Option<Packet> {
+        let packet = match self.state {
+            NetState::Play(_) => self.version.write_play(packet)?,
+            _ => packet,
+        };
+
+        self.capture(capture::Direction::Outbound, &packet);
+        Some(packet)
+    }
+
+    fn capture(&mut self, direction: capture::Direction, packet: &Packet) {
+        let state = self.state.capture_state();
+        let result = match self.capture {
+            Some(ref mut capture) => capture.write(direction, state, packet),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            println!("Stopped capturing packets for {}: {}", self.address, e);
+            self.capture = None;
+        }
+    }
+
+    /// Drops everything queued for a client that isn't keeping up and disconnects it.
+    fn client_too_slow(&mut self) {
+        println!(
+            "Disconnecting {}: client too slow, {} packets ({} bytes) were queued",
+            self.address,
+            self.output.len(),
+            self.output.bytes()
+        );
+
+        // Set first so nothing else is queued, and refused in turn, while disconnecting
+        self.abandoned = true;
+        self.output.clear();
+        // Nothing queued is going out now, but the client is already encrypting and expects
+        // the disconnect to be too
+        for (_, change) in self.codec_changes.drain(..) {
+            if let CodecChange::Encryption = change {
+                self.stream.codec_mut().start_encrypting();
+            }
+        }
+
+        // Goes out even if the cap is smaller than the disconnect itself
+        if let Some(packet) = self
+            .disconnect_packet("Client too slow")
+            .and_then(|packet| self.outgoing(packet))
+        {
+            self.output.push_back_over_cap(packet);
+        }
+        self.closing = true;
+    }
+
+    /// Tells the client why its login failed and closes the connection once that's been sent.
+    fn login_failed(&mut self, error: Error) {
+        println!("Login failed for {}: {}", self.address, error);
+        self.disconnect(&error.to_string());
+    }
+
+    /// Sends the client a disconnect with the given reason if its state has one, then closes
+    /// the connection once everything queued has been written.
+    fn disconnect(&mut self, reason: &str) {
+        if let Some(packet) = self.disconnect_packet(reason) {
+            self.queue(packet);
+        }
+
+        self.closing = true;
+    }
+
+    fn disconnect_packet(&mut self, reason: &str) -> Option<Packet> {
+        let codec = self.stream.codec_mut();
+        match self.state {
+            NetState::Login => Some(codec.encode_packet(&login::disconnect(reason))),
+            NetState::Play(_) => Some(codec.encode_packet(&play::disconnect(reason))),
+            NetState::Handshake | NetState::Status => None,
+        }
+    }
+
+    fn enter_play(&mut self, uuid: Uuid) -> Result<(), Error> {
+        self.set_state(NetState::Play(uuid));
+        play::spawn(self, &uuid)?;
+        self.keep_alive = Some(keep_alive::KeepAlive::new());
+
+        Ok(())
+    }
+
+    fn set_state(&mut self, state: NetState) {
+        let max_packet_size = state.max_packet_size();
+        self.stream.codec_mut().set_max_packet_size(max_packet_size);
+
+        match state {
+            NetState::Login => {
+                self.login_timeout = Some(Delay::new(Instant::now() + LOGIN_TIMEOUT));
+            }
+            NetState::Play(_) => {
+                self.read_timeout = None;
+                self.login_timeout = None;
+            }
+            NetState::Handshake | NetState::Status => (),
+        }
+
+        self.state = state;
+    }
+
+    /// Gives up on a client that has gone quiet or is taking too long to log in. One that isn't
+    /// sending may not be reading either, so it only gets one go at being told why.
+    fn poll_timeouts(&mut self) -> Result<(), Error> {
+        let reason = if expired(&mut self.login_timeout)? {
+            "Took too long to log in"
+        } else if expired(&mut self.read_timeout)? {
+            "Timed out"
+        } else {
+            return Ok(());
+        };
+
+        println!("Disconnecting {}: {}", self.address, reason);
+        self.disconnect(reason);
+        self.abandoned = true;
+
+        Ok(())
+    }
+
+    /// Answers a connection that has only sent the start of a legacy ping once it's clear
+    /// nothing else is coming.
+    fn poll_undecided_ping(&mut self) -> Result<(), Error> {
+        if self.stream.codec().undecided_ping.is_none() {
+            self.legacy_ping_timeout = None;
+            return Ok(());
+        }
+
+        if self.legacy_ping_timeout.is_none() {
+            self.legacy_ping_timeout = Some(Delay::new(Instant::now() + LEGACY_PING_WAIT));
+        }
+
+        if expired(&mut self.legacy_ping_timeout)? {
+            if let Some(packet) = self.stream.codec_mut().take_undecided_ping() {
+                self.capture(capture::Direction::Inbound, &packet);
+                self.answer_legacy_ping(&packet);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn answer_legacy_ping(&mut self, packet: &Packet) {
+        status::send_legacy_status(self, packet);
+        self.closing = true;
+    }
+
+    fn poll_keep_alive(&mut self) {
+        let poll = match self.keep_alive {
+            Some(ref mut keep_alive) if !self.closing => keep_alive.poll(),
+            _ => return,
+        };
+
+        match poll {
+            Ok(Async::Ready(id)) => {
+                play::send_keep_alive(self, id);
+                // Poll again so the deadline registers for when the answer is due
+                self.poll_keep_alive();
+            }
+            Ok(Async::NotReady) => (),
+            Err(e) => self.disconnect(&e.to_string()),
+        }
+    }
+
+    /// Sends Set Compression and compresses everything queued after it, in both directions
+    /// since the client can't send anything compressed before it gets the packet.
+    pub fn set_compression(&mut self, threshold: usize) {
+        self.send(login_packets::clientbound::SetCompression {
+            threshold: VarInt(threshold as i32),
+        });
+        self.change_codec(CodecChange::Compression(threshold));
+    }
+
+    /// Turns on AES/CFB8. What the client sends from here on is already encrypted, but anything
+    /// queued for it before this point still goes out in the clear.
+    fn set_encryption(&mut self, shared_secret: &[u8]) -> Result<(), Error> {
+        let cipher = encryption::Cipher::new(shared_secret)?;
+        self.stream.codec_mut().start_decrypting(cipher);
+        self.change_codec(CodecChange::Encryption);
+        Ok(())
+    }
+
+    fn change_codec(&mut self, change: CodecChange) {
+        let ahead: usize = self.codec_changes.iter().map(|(ahead, _)| ahead).sum();
+        self.codec_changes
+            .push_back((self.output.len() - ahead, change));
+    }
+
+    // Makes the codec changes that have nothing left queued ahead of them
+    fn apply_codec_changes(&mut self) {
+        while let Some(&(0, _)) = self.codec_changes.front() {
+            let codec = self.stream.codec_mut();
+            match self.codec_changes.pop_front() {
+                Some((_, CodecChange::Compression(threshold))) => {
+                    codec.set_compression(Some(threshold))
+                }
+                Some((_, CodecChange::Encryption)) => codec.start_encrypting(),
+                None => (),
+            }
+        }
+    }
+
+    /// Takes on the address a proxy says the client connected to it from. Forwarding doesn't
+    /// include the client's port so the proxy's is kept.
+    fn set_forwarded_address(&mut self, forwarded: &forwarding::Forwarded) {
+        self.address = SocketAddr::new(forwarded.address, self.address.port());
+    }
+
+    /// Records a login attempt from the client's address, returning false if it has to wait.
+    fn allow_login(&self) -> bool {
+        self.connection.allow_login(self.address.ip())
+    }
+
+    fn flush_output(&mut self) -> Result<(), Error> {
+        self.apply_codec_changes();
+        while let Some(packet) = self.output.pop_front() {
+            match self.stream.start_send(packet)? {
+                NotReady(packet) => {
+                    self.output.push_front(packet);
+                    break;
+                }
+                Ready => {
+                    if let Some((ahead, _)) = self.codec_changes.front_mut() {
+                        *ahead -= 1;
+                    }
+                    self.apply_codec_changes();
+                }
+            }
+        }
+
+        Ok(())
     }
 
     fn read_handshake(&mut self, packet: &mut Packet) -> Result<(), Error> {
-        let _version = codec::read_varint(&mut packet.data)?;
-        let _address = codec::read_string(&mut packet.data)?;
-        let _port = codec::read_ushort(&mut packet.data);
-        let state = codec::read_varint(&mut packet.data)?;
+        let handshake: handshake::serverbound::Handshake = packet.read()?;
+        let protocol = handshake.protocol_version.0;
+        let state = handshake.next_state.0;
+        let version = Version::from_protocol(protocol);
+
+        if let Some(version) = version {
+            self.version = version;
+        }
+
+        let forwarding = self.proxy.is_some();
+        let bungeecord = self.proxy == Some(forwarding::Proxy::BungeeCord);
+
+        let length = handshake.server_address.encode_utf16().count();
+        if !bungeecord && length > 255 {
+            return Err(ProtocolError::StringTooLong { length, max: 255 }.into());
+        }
 
         if state == 1 {
-            self.state = NetState::Status;
+            self.set_state(NetState::Status);
         } else if state == 2 {
-            self.state = NetState::Login;
+            self.set_state(NetState::Login);
+
+            // Behind a proxy every player shares its address, so they're throttled by the one it
+            // forwards once that's been read
+            if !forwarding && !self.allow_login() {
+                self.disconnect(THROTTLED);
+            } else if protocol < Version::OLDEST.protocol() {
+                self.disconnect(&format!("Outdated client! Please use {}", crate::VERSION));
+            } else if protocol > Version::LATEST.protocol() {
+                self.disconnect(&format!("Outdated server! I'm still on {}", crate::VERSION));
+            } else if version.is_none() {
+                self.disconnect(&format!(
+                    "Unsupported version! Please use {} to {}",
+                    Version::OLDEST.name(),
+                    crate::VERSION
+                ));
+            } else if bungeecord {
+                match forwarding::read_bungeecord(&handshake.server_address) {
+                    Ok((_, forwarded)) => {
+                        self.set_forwarded_address(&forwarded);
+                        self.forwarded = Some(forwarded);
+
+                        if !self.allow_login() {
+                            self.disconnect(THROTTLED);
+                        }
+                    }
+                    Err(e) => self.login_failed(e),
+                }
+            }
         } else {
-            unimplemented!()
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown next state {} in handshake", state),
+            ));
         }
 
         Ok(())
     }
 }
 
+/// Whether a timeout has passed, registering for when it does if it hasn't.
+fn expired(timeout: &mut Option<Delay>) -> Result<bool, Error> {
+    match timeout {
+        Some(timeout) => Ok(timeout.poll().map_err(Error::other)?.is_ready()),
+        None => Ok(false),
+    }
+}
+
+impl Drop for PlayerSocket {
+    fn drop(&mut self) {
+        if let NetState::Play(uuid) = self.state {
+            if let Ok(mut server) = self.server.write() {
+                // Only the player this connection logged in, which shares its output stats
+                let stats = self.output.stats();
+                let ours = server
+                    .players
+                    .get(&uuid)
+                    .is_some_and(|player| Arc::ptr_eq(&player.output, &stats));
+
+                if ours {
+                    server.remove_player(&uuid);
+                }
+            }
+        }
+    }
+}
+
 pub struct PluginMessage {
     namespace: String,
     channel: String,
-    data: Vec<u8>,
+    data: Bytes,
 }
 
-impl Into<Packet> for PluginMessage {
-    fn into(mut self) -> Packet {
-        self.namespace.push_str(":");
-        self.namespace.push_str(&self.channel);
-
-        let mut data = codec::encode_string(&self.namespace);
-        data.append(&mut self.data);
-        Packet::new(0x19, data)
+impl From<PluginMessage> for Packet {
+    fn from(message: PluginMessage) -> Packet {
+        Packet::encode(&packets::play::clientbound::PluginMessage {
+            channel: message.identifier(),
+            data: packets::Remaining(message.data),
+        })
     }
 }
 
 impl PluginMessage {
-    fn new(namespace: String, channel: String, data: Vec<u8>) -> PluginMessage {
+    fn new(namespace: String, channel: String, data: Bytes) -> PluginMessage {
         PluginMessage {
             namespace,
             channel,
@@ -238,7 +1261,288 @@ impl PluginMessage {
         }
     }
 
-    fn new_minecraft(channel: String, data: Vec<u8>) -> PluginMessage {
+    fn new_minecraft(channel: String, data: Bytes) -> PluginMessage {
         PluginMessage::new(String::from("minecraft"), channel, data)
     }
+
+    fn identifier(&self) -> String {
+        format!("{}:{}", self.namespace, self.channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::net::session::tests::NobodyJoined;
+    use std::net::TcpListener;
+    use tokio::reactor::Handle;
+    use tokio::runtime::current_thread;
+
+    fn decode_all(codec: &mut PacketCodec, bytes: &[u8]) -> Vec<Packet> {
+        let mut src = BytesMut::from(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = codec.decode(&mut src).unwrap() {
+            packets.push(packet);
+        }
+
+        packets
+    }
+
+    fn encode(codec: &mut PacketCodec, id: i32, data: &[u8]) -> BytesMut {
+        let mut dst = BytesMut::new();
+        codec
+            .encode(Packet::new(id, Bytes::from(data)), &mut dst)
+            .unwrap();
+        dst
+    }
+
+    fn compressing(threshold: usize) -> PacketCodec {
+        let mut codec = PacketCodec::new();
+        codec.set_compression(Some(threshold));
+        codec
+    }
+
+    #[test]
+    fn packets_below_the_threshold_are_sent_uncompressed() {
+        let mut codec = compressing(64);
+        let frame = encode(&mut codec, 0x21, &[1, 2, 3]);
+        assert_eq!(&frame[..], &[5, 0, 0x21, 1, 2, 3]);
+
+        let packets = decode_all(&mut codec, &frame);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].id, 0x21);
+        assert_eq!(&packets[0].data[..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn packets_at_the_threshold_are_compressed() {
+        let mut codec = compressing(64);
+        let data = vec![7; 300];
+        let frame = encode(&mut codec, 0x22, &data);
+
+        // Data length is the uncompressed id and data
+        let mut buf = Cursor::new(&frame[..]);
+        let length = codec::read_varint(&mut buf).unwrap() as usize;
+        assert_eq!(buf.position() as usize + length, frame.len());
+        assert_eq!(codec::read_varint(&mut buf).unwrap(), 1 + 300);
+        assert!(frame.len() < 300);
+
+        let packets = decode_all(&mut codec, &frame);
+        assert_eq!(packets[0].id, 0x22);
+        assert_eq!(&packets[0].data[..], &data[..]);
+    }
+
+    #[test]
+    fn compressed_packets_below_the_threshold_are_rejected() {
+        let frame = encode(&mut compressing(16), 0x00, &[0; 32]);
+
+        let mut src = BytesMut::from(&frame[..]);
+        assert!(compressing(64).decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn compressed_packets_must_match_their_data_length() {
+        let mut compressed = ZlibEncoder::new(Vec::new(), Compression::default());
+        compressed.write_all(&[0; 100]).unwrap();
+        let compressed = compressed.finish().unwrap();
+
+        let mut frame = BytesMut::new();
+        codec::write_varint(&mut frame, (1 + compressed.len()) as i32);
+        codec::write_varint(&mut frame, 99);
+        frame.extend_from_slice(&compressed);
+
+        assert!(compressing(64).decode(&mut frame).is_err());
+    }
+
+    #[test]
+    fn encrypted_frames_round_trip() {
+        let secret = [0x5A; 16];
+        let mut sender = PacketCodec::new();
+        sender.set_encryption(encryption::Cipher::new(&secret).unwrap());
+        let mut receiver = PacketCodec::new();
+        receiver.set_encryption(encryption::Cipher::new(&secret).unwrap());
+
+        let mut frames = encode(&mut sender, 0x03, b"hello");
+        frames.extend_from_slice(&encode(&mut sender, 0x03, b"world"));
+        assert!(!frames.windows(5).any(|window| window == b"hello"));
+
+        let packets = decode_all(&mut receiver, &frames);
+        let data: Vec<&[u8]> = packets.iter().map(|packet| &packet.data[..]).collect();
+        assert_eq!(data, vec![&b"hello"[..], &b"world"[..]]);
+    }
+
+    #[test]
+    fn encrypted_frames_can_arrive_a_byte_at_a_time() {
+        let secret = [0x5A; 16];
+        let mut sender = PacketCodec::new();
+        sender.set_encryption(encryption::Cipher::new(&secret).unwrap());
+        let mut receiver = PacketCodec::new();
+        receiver.set_encryption(encryption::Cipher::new(&secret).unwrap());
+
+        let mut frames = encode(&mut sender, 0x03, &[7; 300]);
+        frames.extend_from_slice(&encode(&mut sender, 0x03, b"world"));
+
+        let mut src = BytesMut::new();
+        let mut packets = Vec::new();
+        for byte in frames.iter() {
+            src.extend_from_slice(&[*byte]);
+            if let Some(packet) = receiver.decode(&mut src).unwrap() {
+                packets.push(packet);
+            }
+        }
+
+        let data: Vec<&[u8]> = packets.iter().map(|packet| &packet.data[..]).collect();
+        assert_eq!(data, vec![&[7; 300][..], &b"world"[..]]);
+    }
+
+    // A connection in Login, along with the client's end of it
+    fn logging_in() -> (PlayerSocket, std::net::TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        let stream = Box::new(TcpStream::from_std(stream, &Handle::default()).unwrap());
+
+        let server = Obelisk::new(Config::for_tests(), Arc::new(NobodyJoined));
+        let limiter = Arc::new(throttle::Limiter::new(&server.config));
+        let key = Arc::new(encryption::ServerKey::generate().unwrap());
+        let settings = Settings::new(&server, key);
+        let mut socket = PlayerSocket::new(
+            Arc::new(RwLock::new(server)),
+            &settings,
+            limiter.connect(address.ip()).unwrap(),
+            shutdown::Drain::default().track(),
+            stream,
+            address,
+            BytesMut::new(),
+        );
+        socket.state = NetState::Login;
+
+        (socket, client)
+    }
+
+    // Writes out everything queued, then closes the connection
+    fn flush(mut socket: PlayerSocket) {
+        // Writing to the socket needs a task, and waits for it to become writable
+        current_thread::block_on_all(future::poll_fn(|| {
+            socket.flush_output()?;
+            socket.stream.poll_complete()
+        }))
+        .unwrap();
+        assert!(socket.output.is_empty());
+        assert!(socket.codec_changes.is_empty());
+    }
+
+    #[test]
+    fn codec_changes_wait_for_what_was_queued_before_them() {
+        let secret = [0x5A; 16];
+        let (mut socket, mut client) = logging_in();
+        socket.send_packet(0x10, Bytes::from(vec![1; 300]));
+        socket.set_compression(64);
+        socket.send_packet(0x11, Bytes::from(vec![2; 300]));
+        socket.set_encryption(&secret).unwrap();
+        socket.send_packet(0x12, Bytes::from(vec![3; 300]));
+
+        flush(socket);
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).unwrap();
+        let mut src = BytesMut::from(written);
+        let mut codec = PacketCodec::client();
+        let mut next = |codec: &mut PacketCodec| codec.decode(&mut src).unwrap().unwrap();
+
+        let before = next(&mut codec);
+        assert_eq!((before.id, &before.data[..]), (0x10, &[1; 300][..]));
+        assert_eq!(next(&mut codec).id, 0x03);
+        codec.set_compression(Some(64));
+        let compressed = next(&mut codec);
+        assert_eq!((compressed.id, &compressed.data[..]), (0x11, &[2; 300][..]));
+        codec.set_encryption(encryption::Cipher::new(&secret).unwrap());
+        let encrypted = next(&mut codec);
+        assert_eq!((encrypted.id, &encrypted.data[..]), (0x12, &[3; 300][..]));
+    }
+
+    #[test]
+    fn clients_too_slow_for_a_disconnect_are_still_sent_one() {
+        let (mut socket, mut client) = logging_in();
+        // Smaller than the disconnect itself, which loading the config doesn't allow
+        socket.output = output::OutputQueue::new(16);
+        socket.send_packet(0x10, Bytes::from(vec![1; 300]));
+        assert!(socket.abandoned && socket.closing);
+        assert_eq!(socket.output.len(), 1);
+        flush(socket);
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).unwrap();
+        let disconnect = PacketCodec::client()
+            .decode(&mut BytesMut::from(written))
+            .unwrap()
+            .unwrap();
+        assert_eq!(disconnect.id, login_packets::clientbound::Disconnect::ID);
+    }
+
+    #[test]
+    fn legacy_pings_are_recognised() {
+        // 1.6 sends the rest of its ping host after this, which is passed along with it
+        let ping_1_6 = [LEGACY_PING, 0x01, LEGACY_PING_HOST, 0x00, 0x0B];
+        let mut codec = PacketCodec::new();
+        let packets = decode_all(&mut codec, &ping_1_6);
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].id, LEGACY_PING as i32);
+        assert_eq!(&packets[0].data[..], &ping_1_6[1..]);
+        assert!(codec.legacy);
+        assert!(codec.take_undecided_ping().is_none());
+    }
+
+    #[test]
+    fn older_legacy_pings_wait_to_be_taken() {
+        for ping in &[&[LEGACY_PING][..], &[LEGACY_PING, 0x01]] {
+            let mut codec = PacketCodec::new();
+            assert!(decode_all(&mut codec, ping).is_empty());
+            assert!(codec.awaiting_handshake);
+
+            let packet = codec.take_undecided_ping().unwrap();
+            assert_eq!(packet.id, LEGACY_PING as i32);
+            assert_eq!(&packet.data[..], &ping[1..]);
+            assert!(codec.legacy);
+        }
+    }
+
+    #[test]
+    fn handshakes_split_after_0xfe_0x01_are_not_legacy_pings() {
+        // A 254 byte frame's length is 0xFE 0x01, the same as a 1.4 ping
+        let data = vec![0x2A; 253];
+        let frame = encode(&mut PacketCodec::new(), 0x00, &data);
+        assert_eq!(frame[..2], [LEGACY_PING, 0x01]);
+
+        let mut codec = PacketCodec::new();
+        let mut src = BytesMut::from(&frame[..2]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(codec.awaiting_handshake);
+
+        src.extend_from_slice(&frame[2..]);
+        let packet = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!((packet.id, &packet.data[..]), (0x00, &data[..]));
+        assert!(!codec.legacy);
+        assert!(codec.take_undecided_ping().is_none());
+    }
+
+    #[test]
+    fn frames_starting_with_0xfe_are_not_legacy_pings() {
+        // Lengths of 254 and 382 both start with 0xFE, the same as a legacy ping
+        for length in &[254, 382] {
+            let data = vec![0x2A; length - 1];
+            let frame = encode(&mut PacketCodec::new(), 0x00, &data);
+            assert_eq!(frame[0], LEGACY_PING);
+
+            let mut codec = PacketCodec::new();
+            let packets = decode_all(&mut codec, &frame);
+
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].id, 0x00);
+            assert_eq!(&packets[0].data[..], &data[..]);
+            assert!(!codec.legacy);
+        }
+    }
 }
@@ -0,0 +1,164 @@
+use super::{codec, Packet};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Packets waiting to be handed to a connection's codec, capped by their total size so a client
+/// that stops reading can't hold on to an unbounded amount of memory.
+pub struct OutputQueue {
+    packets: VecDeque<Packet>,
+    bytes: usize,
+    max_bytes: usize,
+    stats: Arc<QueueStats>,
+}
+
+/// How much output is waiting on a client, shared so it can be checked from outside the
+/// connection.
+#[derive(Default)]
+pub struct QueueStats {
+    packets: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl QueueStats {
+    pub fn packets(&self) -> usize {
+        self.packets.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+impl OutputQueue {
+    pub fn new(max_bytes: usize) -> OutputQueue {
+        OutputQueue {
+            packets: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+            stats: Arc::new(QueueStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<QueueStats> {
+        self.stats.clone()
+    }
+
+    /// Queues a packet, or hands it back if that would go over the cap.
+    pub fn push_back(&mut self, packet: Packet) -> Result<(), Packet> {
+        let size = size(&packet);
+        if self.bytes + size > self.max_bytes {
+            return Err(packet);
+        }
+
+        self.bytes += size;
+        self.packets.push_back(packet);
+        self.update_stats();
+        Ok(())
+    }
+
+    /// Queues a packet whatever the cap. Only for the disconnect a client that fell behind is
+    /// sent once everything else has been cleared.
+    pub fn push_back_over_cap(&mut self, packet: Packet) {
+        self.bytes += size(&packet);
+        self.packets.push_back(packet);
+        self.update_stats();
+    }
+
+    /// Puts back a packet the codec couldn't take yet. It was counted when it was first queued
+    /// so this never goes over the cap.
+    pub fn push_front(&mut self, packet: Packet) {
+        self.bytes += size(&packet);
+        self.packets.push_front(packet);
+        self.update_stats();
+    }
+
+    pub fn pop_front(&mut self) -> Option<Packet> {
+        let packet = self.packets.pop_front()?;
+        self.bytes -= size(&packet);
+        self.update_stats();
+        Some(packet)
+    }
+
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.bytes = 0;
+        self.update_stats();
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    fn update_stats(&self) {
+        self.stats
+            .packets
+            .store(self.packets.len(), Ordering::Relaxed);
+        self.stats.bytes.store(self.bytes, Ordering::Relaxed);
+    }
+}
+
+// Size of the packet's id and data, before framing, compression and encryption
+fn size(packet: &Packet) -> usize {
+    codec::varint_size(packet.id) + packet.data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn packet(length: usize) -> Packet {
+        Packet::new(0x20, Bytes::from(vec![0; length]))
+    }
+
+    #[test]
+    fn packets_over_the_cap_are_handed_back() {
+        let mut queue = OutputQueue::new(20);
+        assert!(queue.push_back(packet(9)).is_ok());
+        assert!(queue.push_back(packet(9)).is_ok());
+
+        let rejected = queue.push_back(packet(1)).unwrap_err();
+        assert_eq!(rejected.data.len(), 1);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.bytes(), 20);
+    }
+
+    #[test]
+    fn stats_follow_the_queue() {
+        let mut queue = OutputQueue::new(100);
+        let stats = queue.stats();
+        assert!(queue.push_back(packet(9)).is_ok());
+        assert!(queue.push_back(packet(19)).is_ok());
+        assert_eq!((stats.packets(), stats.bytes()), (2, 30));
+
+        let first = queue.pop_front().unwrap();
+        assert_eq!((stats.packets(), stats.bytes()), (1, 20));
+
+        // Putting back what was just taken out always fits
+        queue.push_front(first);
+        assert_eq!((stats.packets(), stats.bytes()), (2, 30));
+    }
+
+    #[test]
+    fn clearing_an_abandoned_queue_frees_everything() {
+        let mut queue = OutputQueue::new(10);
+        let stats = queue.stats();
+        assert!(queue.push_back(packet(9)).is_ok());
+        assert!(queue.push_back(packet(9)).is_err());
+
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.bytes(), 0);
+        assert_eq!((stats.packets(), stats.bytes()), (0, 0));
+        assert!(queue.push_back(packet(9)).is_ok());
+    }
+}
@@ -0,0 +1,15 @@
+pub mod serverbound {
+    use crate::net::packets::VarInt;
+
+    packets! {
+        ServerboundPacket {
+            0x00 => Handshake {
+                protocol_version: VarInt,
+                // Limited to 255 in `read_handshake`, BungeeCord forwarding makes it longer
+                server_address: String,
+                server_port: u16,
+                next_state: VarInt,
+            },
+        }
+    }
+}
@@ -0,0 +1,50 @@
+pub mod clientbound {
+    use crate::net::packets::{Remaining, VarInt};
+
+    packets! {
+        ClientboundPacket {
+            0x00 => Disconnect {
+                reason: String,
+            },
+            0x01 => EncryptionRequest {
+                server_id: String,
+                public_key: Vec<u8>,
+                verify_token: Vec<u8>,
+            },
+            0x02 => LoginSuccess {
+                uuid: String,
+                username: String,
+            },
+            0x03 => SetCompression {
+                threshold: VarInt,
+            },
+            0x04 => LoginPluginRequest {
+                message_id: VarInt,
+                channel: String,
+                data: Remaining,
+            },
+        }
+    }
+}
+
+pub mod serverbound {
+    use crate::net::packets::{Remaining, VarInt};
+
+    packets! {
+        ServerboundPacket {
+            0x00 => LoginStart {
+                #[max_length = 16]
+                username: String,
+            },
+            0x01 => EncryptionResponse {
+                shared_secret: Vec<u8>,
+                verify_token: Vec<u8>,
+            },
+            0x02 => LoginPluginResponse {
+                message_id: VarInt,
+                successful: bool,
+                data: Remaining,
+            },
+        }
+    }
+}
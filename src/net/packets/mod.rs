@@ -0,0 +1,433 @@
+use super::codec::{self, ProtocolError};
+use crate::world::Vector;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io::Cursor;
+use uuid::Uuid;
+
+/// Something that can be written to and read back from the body of a packet. Every packet is
+/// itself a field made up of the fields it declares.
+pub trait Field: Sized {
+    /// How many bytes `encode` writes, so space for them can be reserved up front.
+    fn size(&self) -> usize;
+    fn encode<B: BufMut>(&self, buf: &mut B);
+    /// Reads the field from a packet's data, which fields can hold on to without copying.
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError>;
+}
+
+pub trait ClientboundPacket: Field {
+    const ID: i32;
+}
+
+pub trait ServerboundPacket: Field {
+    const ID: i32;
+}
+
+/// Declares packet structs for one direction of a connection state, deriving their field
+/// encoding in declaration order along with their id in that state. String fields can be given
+/// a tighter limit than the protocol default with `#[max_length = n]`. Each module also gets a
+/// `describe` function which decodes any of its packets by id for debugging.
+macro_rules! packets {
+    ($direction:ident {
+        $($id:literal => $name:ident {
+            $($(#[max_length = $max:literal])? $field:ident: $ty:ty),* $(,)?
+        }),* $(,)?
+    }) => {
+        $(
+            #[derive(Clone, Debug, PartialEq)]
+            pub struct $name {
+                $(pub $field: $ty),*
+            }
+
+            impl $crate::net::packets::Field for $name {
+                fn size(&self) -> usize {
+                    0 $(+ $crate::net::packets::Field::size(&self.$field))*
+                }
+
+                fn encode<B: bytes::BufMut>(&self, _buf: &mut B) {
+                    $($crate::net::packets::Field::encode(&self.$field, _buf);)*
+                }
+
+                fn decode(
+                    _buf: &mut std::io::Cursor<bytes::Bytes>,
+                ) -> Result<Self, $crate::net::codec::ProtocolError> {
+                    Ok($name {
+                        $($field: decode_field!(_buf $(, $max)?)),*
+                    })
+                }
+            }
+
+            impl $crate::net::packets::$direction for $name {
+                const ID: i32 = $id;
+            }
+        )*
+
+        /// Decodes the packet with the given id for debugging, or returns `None` if it isn't
+        /// one declared here.
+        pub fn describe(
+            id: i32,
+            buf: &mut std::io::Cursor<bytes::Bytes>,
+        ) -> Option<Result<String, $crate::net::codec::ProtocolError>> {
+            match id {
+                $($id => Some(
+                    <$name as $crate::net::packets::Field>::decode(buf)
+                        .map(|packet| format!("{:?}", packet)),
+                ),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+macro_rules! decode_field {
+    ($buf:ident) => {
+        $crate::net::packets::Field::decode($buf)?
+    };
+    ($buf:ident, $max:literal) => {
+        $crate::net::codec::read_string($buf, $max)?
+    };
+}
+
+// Allocated at a time by `encode_into`, enough for most packets to share one allocation
+const ENCODE_BUF_SIZE: usize = 8 * 1024;
+
+// State modules come after `packets!` so the macro is in scope for them
+pub mod handshake;
+pub mod login;
+pub mod play;
+pub mod play_1_14;
+pub mod status;
+
+/// A protocol VarInt, as opposed to a plain `i32` which is written as a big endian Int.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VarInt(pub i32);
+
+/// The rest of the packet's data, left uninterpreted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Remaining(pub Bytes);
+
+impl Field for bool {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_bool(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_bool(buf)
+    }
+}
+
+impl Field for i8 {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_byte(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_byte(buf)
+    }
+}
+
+impl Field for u8 {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_ubyte(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_ubyte(buf)
+    }
+}
+
+impl Field for i16 {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_short(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_short(buf)
+    }
+}
+
+impl Field for u16 {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_ushort(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_ushort(buf)
+    }
+}
+
+impl Field for i32 {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_int(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_int(buf)
+    }
+}
+
+impl Field for i64 {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_long(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_long(buf)
+    }
+}
+
+impl Field for f32 {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_float(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_float(buf)
+    }
+}
+
+impl Field for f64 {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_double(buf, *self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_double(buf)
+    }
+}
+
+impl Field for VarInt {
+    fn size(&self) -> usize {
+        codec::varint_size(self.0)
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_varint(buf, self.0);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_varint(buf).map(VarInt)
+    }
+}
+
+impl Field for String {
+    fn size(&self) -> usize {
+        codec::varint_size(self.len() as i32) + self.len()
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_string(buf, self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_string(buf, codec::MAX_STRING_LENGTH)
+    }
+}
+
+impl Field for Uuid {
+    fn size(&self) -> usize {
+        16
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_uuid(buf, self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_uuid(buf)
+    }
+}
+
+/// Block positions, packed into a Long.
+impl Field for Vector {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_position(buf, self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_position(buf)
+    }
+}
+
+/// Byte arrays are prefixed with their length as a VarInt.
+impl Field for Vec<u8> {
+    fn size(&self) -> usize {
+        codec::varint_size(self.len() as i32) + self.len()
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_byte_array(buf, self);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_byte_array(buf)
+    }
+}
+
+impl Field for Remaining {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&self.0);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        // A slice of the packet's own buffer rather than a copy of it
+        let start = buf.position() as usize;
+        let data = buf.get_ref().slice_from(start);
+        buf.set_position(buf.get_ref().len() as u64);
+
+        Ok(Remaining(data))
+    }
+}
+
+/// Encodes `field` onto the end of `buf` and splits it off, so fields encoded one after another
+/// share `buf`'s allocation instead of each needing their own.
+pub fn encode_into<F: Field>(buf: &mut BytesMut, field: &F) -> Bytes {
+    let size = field.size();
+    if buf.capacity() - buf.len() < size {
+        buf.reserve(size.max(ENCODE_BUF_SIZE));
+    }
+
+    field.encode(buf);
+    buf.take().freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Buf, IntoBuf};
+    use std::fmt::Debug;
+
+    fn round_trip<P: Field + Debug + PartialEq>(packet: P) {
+        let mut data = Vec::new();
+        packet.encode(&mut data);
+
+        let mut buf = Bytes::from(data).into_buf();
+        assert_eq!(P::decode(&mut buf).unwrap(), packet);
+        assert_eq!(buf.remaining(), 0, "{:?} left data unread", packet);
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        round_trip(handshake::serverbound::Handshake {
+            protocol_version: VarInt(498),
+            server_address: String::from("play.example.com"),
+            server_port: 25565,
+            next_state: VarInt(2),
+        });
+        round_trip(login::clientbound::EncryptionRequest {
+            server_id: String::new(),
+            public_key: vec![0x30, 0x81, 0x9F],
+            verify_token: vec![1, 2, 3, 4],
+        });
+        round_trip(login::serverbound::LoginPluginResponse {
+            message_id: VarInt(-1),
+            successful: true,
+            data: Remaining(Bytes::from(&b"player info"[..])),
+        });
+        round_trip(play::clientbound::JoinGame {
+            entity_id: -7,
+            gamemode: 1,
+            dimension: -1,
+            difficulty: 0,
+            max_players: 0,
+            level_type: String::from("default"),
+            reduced_debug_info: false,
+        });
+        round_trip(play::clientbound::PosLook {
+            x: 0.5,
+            y: 64.0,
+            z: -1234.25,
+            yaw: 90.0,
+            pitch: -45.5,
+            flags: 0,
+            teleport_id: VarInt(i32::MAX),
+        });
+        round_trip(play::clientbound::SpawnPosition {
+            location: Vector {
+                x: -30_000_000.0,
+                y: 255.0,
+                z: 29_999_999.0,
+            },
+        });
+        round_trip(play::serverbound::ClientSettings {
+            locale: String::from("en_gb"),
+            view_distance: 12,
+            chat_mode: VarInt(1),
+            chat_colors: true,
+            skin_parts: 0x7F,
+            main_hand: VarInt(0),
+        });
+    }
+
+    #[test]
+    fn truncated_packets_fail_to_decode() {
+        let mut data = Vec::new();
+        play::clientbound::KeepAlive { id: 42 }.encode(&mut data);
+        data.pop();
+
+        let mut buf = Bytes::from(data).into_buf();
+        match play::clientbound::KeepAlive::decode(&mut buf) {
+            Err(ProtocolError::ShortRead { needed, remaining }) => {
+                assert_eq!((needed, remaining), (8, 7))
+            }
+            result => panic!("Expected a short read, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn string_limits_are_enforced() {
+        let mut data = Vec::new();
+        codec::write_string(&mut data, "SeventeenCharName");
+
+        let mut buf = Bytes::from(data).into_buf();
+        match login::serverbound::LoginStart::decode(&mut buf) {
+            Err(ProtocolError::StringTooLong { length, max }) => {
+                assert_eq!((length, max), (17, 16))
+            }
+            result => panic!("Expected the username to be too long, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Declared with their 1.13.2 ids and layout, see `net::version` for how other versions map
+pub mod clientbound {
+    use crate::net::packets::{Remaining, VarInt};
+    use crate::world::Vector;
+
+    packets! {
+        ClientboundPacket {
+            0x0D => ServerDifficulty {
+                difficulty: u8,
+            },
+            0x19 => PluginMessage {
+                channel: String,
+                data: Remaining,
+            },
+            0x1B => Disconnect {
+                reason: String,
+            },
+            0x21 => KeepAlive {
+                id: i64,
+            },
+            0x25 => JoinGame {
+                entity_id: i32,
+                gamemode: u8,
+                dimension: i32,
+                difficulty: u8,
+                max_players: u8,
+                level_type: String,
+                reduced_debug_info: bool,
+            },
+            0x2E => Abilities {
+                flags: i8,
+                flying_speed: f32,
+                fov_modifier: f32,
+            },
+            0x32 => PosLook {
+                x: f64,
+                y: f64,
+                z: f64,
+                yaw: f32,
+                pitch: f32,
+                flags: i8,
+                teleport_id: VarInt,
+            },
+            0x49 => SpawnPosition {
+                location: Vector,
+            },
+        }
+    }
+}
+
+// Packets carrying item slots, NBT or optional fields aren't declared yet
+pub mod serverbound {
+    use crate::net::packets::{Remaining, VarInt};
+    use crate::world::Vector;
+    use uuid::Uuid;
+
+    packets! {
+        ServerboundPacket {
+            0x00 => TeleportConfirm {
+                teleport_id: VarInt,
+            },
+            0x01 => QueryBlockNbt {
+                transaction_id: VarInt,
+                location: Vector,
+            },
+            0x02 => Chat {
+                #[max_length = 256]
+                message: String,
+            },
+            0x03 => ClientStatus {
+                action: VarInt,
+            },
+            0x04 => ClientSettings {
+                #[max_length = 16]
+                locale: String,
+                view_distance: i8,
+                chat_mode: VarInt,
+                chat_colors: bool,
+                skin_parts: u8,
+                main_hand: VarInt,
+            },
+            0x05 => TabComplete {
+                transaction_id: VarInt,
+                #[max_length = 32500]
+                text: String,
+            },
+            0x06 => ConfirmTransaction {
+                window_id: i8,
+                action: i16,
+                accepted: bool,
+            },
+            0x07 => EnchantItem {
+                window_id: i8,
+                enchantment: i8,
+            },
+            0x09 => CloseWindow {
+                window_id: u8,
+            },
+            0x0A => PluginMessage {
+                channel: String,
+                data: Remaining,
+            },
+            0x0C => QueryEntityNbt {
+                transaction_id: VarInt,
+                entity_id: VarInt,
+            },
+            0x0E => KeepAlive {
+                id: i64,
+            },
+            0x0F => Player {
+                on_ground: bool,
+            },
+            0x10 => Position {
+                x: f64,
+                y: f64,
+                z: f64,
+                on_ground: bool,
+            },
+            0x11 => PosLook {
+                x: f64,
+                y: f64,
+                z: f64,
+                yaw: f32,
+                pitch: f32,
+                on_ground: bool,
+            },
+            0x12 => Look {
+                yaw: f32,
+                pitch: f32,
+                on_ground: bool,
+            },
+            0x13 => VehicleMove {
+                x: f64,
+                y: f64,
+                z: f64,
+                yaw: f32,
+                pitch: f32,
+            },
+            0x14 => SteerBoat {
+                left_paddle: bool,
+                right_paddle: bool,
+            },
+            0x15 => PickItem {
+                slot: VarInt,
+            },
+            0x16 => CraftRecipeRequest {
+                window_id: i8,
+                recipe: String,
+                make_all: bool,
+            },
+            0x17 => Abilities {
+                flags: i8,
+                flying_speed: f32,
+                walking_speed: f32,
+            },
+            0x18 => Digging {
+                status: VarInt,
+                location: Vector,
+                face: i8,
+            },
+            0x19 => EntityAction {
+                entity_id: VarInt,
+                action: VarInt,
+                jump_boost: VarInt,
+            },
+            0x1A => SteerVehicle {
+                sideways: f32,
+                forward: f32,
+                flags: u8,
+            },
+            0x1C => NameItem {
+                #[max_length = 32767]
+                name: String,
+            },
+            0x1D => ResourcePackStatus {
+                result: VarInt,
+            },
+            0x1F => SelectTrade {
+                slot: VarInt,
+            },
+            0x20 => SetBeaconEffect {
+                primary_effect: VarInt,
+                secondary_effect: VarInt,
+            },
+            0x21 => HeldItemChange {
+                slot: i16,
+            },
+            0x26 => UpdateSign {
+                location: Vector,
+                #[max_length = 384]
+                line_1: String,
+                #[max_length = 384]
+                line_2: String,
+                #[max_length = 384]
+                line_3: String,
+                #[max_length = 384]
+                line_4: String,
+            },
+            0x27 => Animation {
+                hand: VarInt,
+            },
+            0x28 => Spectate {
+                target: Uuid,
+            },
+            0x29 => BlockPlacement {
+                location: Vector,
+                face: VarInt,
+                hand: VarInt,
+                cursor_x: f32,
+                cursor_y: f32,
+                cursor_z: f32,
+            },
+            0x2A => UseItem {
+                hand: VarInt,
+            },
+        }
+    }
+}
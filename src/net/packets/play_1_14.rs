@@ -0,0 +1,92 @@
+// Play packets whose fields changed in 1.14, declared with their 1.14 ids. Every other packet
+// kept its 1.13 layout and only needs its id changed, see `net::version`.
+
+use crate::net::codec::{self, ProtocolError};
+use crate::net::packets::Field;
+use crate::world::Vector;
+use bytes::{BufMut, Bytes};
+use std::io::Cursor;
+
+/// A block position packed the 1.14 way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position(pub Vector);
+
+impl Field for Position {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        codec::write_position_1_14(buf, &self.0);
+    }
+
+    fn decode(buf: &mut Cursor<Bytes>) -> Result<Self, ProtocolError> {
+        codec::read_position_1_14(buf).map(Position)
+    }
+}
+
+pub mod clientbound {
+    use super::Position;
+    use crate::net::packets::VarInt;
+
+    packets! {
+        ClientboundPacket {
+            0x0D => ServerDifficulty {
+                difficulty: u8,
+                locked: bool,
+            },
+            // Difficulty moved out to Server Difficulty
+            0x25 => JoinGame {
+                entity_id: i32,
+                gamemode: u8,
+                dimension: i32,
+                max_players: u8,
+                level_type: String,
+                view_distance: VarInt,
+                reduced_debug_info: bool,
+            },
+            0x4D => SpawnPosition {
+                location: Position,
+            },
+        }
+    }
+}
+
+pub mod serverbound {
+    use super::Position;
+    use crate::net::packets::VarInt;
+
+    packets! {
+        ServerboundPacket {
+            0x01 => QueryBlockNbt {
+                transaction_id: VarInt,
+                location: Position,
+            },
+            0x1A => Digging {
+                status: VarInt,
+                location: Position,
+                face: i8,
+            },
+            0x29 => UpdateSign {
+                location: Position,
+                #[max_length = 384]
+                line_1: String,
+                #[max_length = 384]
+                line_2: String,
+                #[max_length = 384]
+                line_3: String,
+                #[max_length = 384]
+                line_4: String,
+            },
+            0x2C => BlockPlacement {
+                hand: VarInt,
+                location: Position,
+                face: VarInt,
+                cursor_x: f32,
+                cursor_y: f32,
+                cursor_z: f32,
+                inside_block: bool,
+            },
+        }
+    }
+}
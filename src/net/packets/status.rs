@@ -0,0 +1,23 @@
+pub mod clientbound {
+    packets! {
+        ClientboundPacket {
+            0x00 => Response {
+                json: String,
+            },
+            0x01 => Pong {
+                payload: i64,
+            },
+        }
+    }
+}
+
+pub mod serverbound {
+    packets! {
+        ServerboundPacket {
+            0x00 => Request {},
+            0x01 => Ping {
+                payload: i64,
+            },
+        }
+    }
+}
@@ -1,31 +1,243 @@
-use super::codec;
-use super::PlayerSocket;
-use tokio::io::Error;
+use super::packets::play::{clientbound, serverbound};
+use super::packets::ServerboundPacket;
+use super::packets::VarInt;
+use super::{channels, Packet, PlayerSocket};
+use crate::entities::player::{Gamemode, Player, Settings};
+use serde_json::json;
+use tokio::io::{Error, ErrorKind};
 use uuid::Uuid;
 
-pub fn handle_play(socket: &mut PlayerSocket) -> Result<(), Error> {
-    //send_join_game(stream, server, player)?;
-    //send_spawn_position(stream, server)?;
-    //send_player_abilities(stream, server)?;
-    //send_player_position_and_look(stream, server)?;
+type Handler = fn(&mut PlayerSocket, &Uuid, &mut Packet) -> Result<(), Error>;
 
-    Ok(())
+macro_rules! handlers {
+    ($($packet:ident),* $(,)?) => {
+        &[$((serverbound::$packet::ID, dispatch::<serverbound::$packet>)),*]
+    };
+}
+
+/// Handlers for serverbound packets in Play, keyed by packet id. Packets without an entry are
+/// dropped without being read.
+const HANDLERS: &[(i32, Handler)] = handlers![
+    TeleportConfirm,
+    Chat,
+    ClientSettings,
+    PluginMessage,
+    KeepAlive,
+    Player,
+    Position,
+    PosLook,
+    Look,
+];
+
+/// A serverbound Play packet the server reacts to.
+trait Handle: ServerboundPacket {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error>;
+}
+
+fn dispatch<P: Handle>(
+    socket: &mut PlayerSocket,
+    uuid: &Uuid,
+    packet: &mut Packet,
+) -> Result<(), Error> {
+    packet.read::<P>()?.handle(socket, uuid)
+}
+
+pub fn handle_play(
+    socket: &mut PlayerSocket,
+    uuid: &Uuid,
+    packet: &mut Packet,
+) -> Result<(), Error> {
+    match HANDLERS.iter().find(|(id, _)| *id == packet.id) {
+        Some((_, handler)) => handler(socket, uuid, packet),
+        None => Ok(()),
+    }
+}
+
+/// Runs `f` on the socket's player, if it's still on the server.
+fn with_player<T, F>(socket: &PlayerSocket, uuid: &Uuid, f: F) -> Option<T>
+where
+    F: FnOnce(&mut Player) -> T,
+{
+    let mut server = socket.server.write().unwrap();
+    server.players.get_mut(uuid).map(f)
+}
+
+impl Handle for serverbound::TeleportConfirm {
+    fn handle(self, socket: &mut PlayerSocket, _uuid: &Uuid) -> Result<(), Error> {
+        // Confirms for an earlier teleport still arrive after another has been sent, so anything
+        // but the latest is ignored, as vanilla does
+        if socket.pending_teleport == Some(self.teleport_id.0) {
+            socket.pending_teleport = None;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::Chat {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        if let Some(player) = socket.server.read().unwrap().players.get(uuid) {
+            println!("<{}> {}", player.username, self.message);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::ClientSettings {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        with_player(socket, uuid, |player| {
+            player.settings = Settings {
+                locale: self.locale,
+                view_distance: self.view_distance,
+                chat_mode: self.chat_mode.0,
+                chat_colors: self.chat_colors,
+                skin_parts: self.skin_parts,
+                main_hand: self.main_hand.0,
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::PluginMessage {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        channels::receive(socket, uuid, &self.channel, self.data.0)
+    }
+}
+
+impl Handle for serverbound::KeepAlive {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        let round_trip = match socket.keep_alive {
+            Some(ref mut pending) => pending.receive(self.id)?,
+            None => return Err(Error::new(ErrorKind::InvalidData, "Unexpected keep alive")),
+        };
+
+        with_player(socket, uuid, |player| {
+            // Smoothed the same way as vanilla so one slow response doesn't spike the ping
+            let round_trip = round_trip.as_millis() as u32;
+            player.latency = (player.latency * 3 + round_trip) / 4;
+        });
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::Player {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        if socket.pending_teleport.is_some() {
+            return Ok(());
+        }
+
+        with_player(socket, uuid, |player| player.on_ground = self.on_ground);
+        Ok(())
+    }
+}
+
+// Past these vanilla clamps positions, and it would have to load chunks at them
+const MAX_HORIZONTAL: f64 = 3.0e7;
+const MAX_VERTICAL: f64 = 2.0e7;
+
+/// Fails unless a position sent by the client is somewhere a player can be.
+fn check_position(x: f64, y: f64, z: f64) -> Result<(), Error> {
+    let valid = |value: f64, max: f64| value.is_finite() && value.abs() <= max;
+    if valid(x, MAX_HORIZONTAL) && valid(y, MAX_VERTICAL) && valid(z, MAX_HORIZONTAL) {
+        Ok(())
+    } else {
+        Err(invalid_move())
+    }
+}
+
+fn check_rotation(yaw: f32, pitch: f32) -> Result<(), Error> {
+    if yaw.is_finite() && pitch.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_move())
+    }
+}
+
+fn invalid_move() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "Invalid move player packet received",
+    )
+}
+
+impl Handle for serverbound::Position {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        check_position(self.x, self.y, self.z)?;
+        if socket.pending_teleport.is_some() {
+            return Ok(());
+        }
+
+        with_player(socket, uuid, |player| {
+            player.location.x = self.x;
+            player.location.y = self.y;
+            player.location.z = self.z;
+            player.on_ground = self.on_ground;
+        });
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::PosLook {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        check_position(self.x, self.y, self.z)?;
+        check_rotation(self.yaw, self.pitch)?;
+        if socket.pending_teleport.is_some() {
+            return Ok(());
+        }
+
+        with_player(socket, uuid, |player| {
+            player.location.x = self.x;
+            player.location.y = self.y;
+            player.location.z = self.z;
+            player.location.yaw = self.yaw;
+            player.location.pitch = self.pitch;
+            player.on_ground = self.on_ground;
+        });
+
+        Ok(())
+    }
+}
+
+impl Handle for serverbound::Look {
+    fn handle(self, socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+        check_rotation(self.yaw, self.pitch)?;
+        if socket.pending_teleport.is_some() {
+            return Ok(());
+        }
+
+        with_player(socket, uuid, |player| {
+            player.location.yaw = self.yaw;
+            player.location.pitch = self.pitch;
+            player.on_ground = self.on_ground;
+        });
+
+        Ok(())
+    }
 }
 
 pub fn spawn(socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
-    send_join_game(socket, uuid);
+    send_join_game(socket, uuid)?;
+    channels::send_server_channels(socket);
+    send_server_difficulty(socket);
+    send_spawn_position(socket);
+    send_abilities(socket);
+    send_position_and_look(socket, uuid);
 
     Ok(())
 }
 
-fn send_join_game(socket: &mut PlayerSocket, uuid: &Uuid) {
-    let mut data = Vec::new();
-
-    {
+fn send_join_game(socket: &mut PlayerSocket, uuid: &Uuid) -> Result<(), Error> {
+    let join_game = {
         let server = socket.server.read().unwrap();
-        let player = server.players.get(&uuid).unwrap();
-
-        data.append(&mut codec::encode_int(player.entity_id));
+        let player = server
+            .players
+            .get(uuid)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Player left before spawning"))?;
 
         let spawn_world = server
             .spawn_location
@@ -38,55 +250,101 @@ fn send_join_game(socket: &mut PlayerSocket, uuid: &Uuid) {
             gamemode |= 0b100;
         }
 
-        data.append(&mut codec::encode_ubyte(gamemode));
-        data.append(&mut codec::encode_int(spawn_world.dimension as i32));
-        data.append(&mut codec::encode_ubyte(spawn_world.difficulty as u8));
-        data.append(&mut codec::encode_ubyte(0)); // Ignored max players
-        data.append(&mut codec::encode_string(
-            spawn_world.level_type.to_string(),
-        ));
-        data.append(&mut codec::encode_bool(false)); // Optional debug values
-    }
+        clientbound::JoinGame {
+            entity_id: player.entity_id,
+            gamemode,
+            dimension: spawn_world.dimension as i32,
+            difficulty: spawn_world.difficulty as u8,
+            max_players: 0, // Ignored by the client
+            level_type: spawn_world.level_type.to_string(),
+            reduced_debug_info: false,
+        }
+    };
 
-    socket.send_packet(0x25, data);
+    socket.send(join_game);
+    Ok(())
 }
 
-/*
-fn send_spawn_position(stream: &mut TcpStream, server: &crate::Obelisk) -> Result<(), Error> {
-    let data = codec::encode_position(&server.spawn_location.to_vector());
+// Part of Join Game before 1.14, where sending it again does no harm
+fn send_server_difficulty(socket: &mut PlayerSocket) {
+    let difficulty = socket
+        .server
+        .read()
+        .unwrap()
+        .spawn_location
+        .world
+        .upgrade()
+        .expect("Spawn world does not exist")
+        .difficulty;
 
-    super::send_packet(stream, 0x49, &data)
+    socket.send(clientbound::ServerDifficulty {
+        difficulty: difficulty as u8,
+    });
 }
 
-fn send_player_abilities(stream: &mut TcpStream, server: &crate::Obelisk) -> Result<(), Error> {
-    let mut data = Vec::new();
+fn send_spawn_position(socket: &mut PlayerSocket) {
+    let location = socket.server.read().unwrap().spawn_location.to_vector();
+    socket.send(clientbound::SpawnPosition { location });
+}
 
-    let abilities = if server.spawn_location.world.upgrade()
-        .expect("Spawn world does not exist").gamemode == player::Gamemode::Survival {
-        0
-    } else {
-        0b1111
+fn send_abilities(socket: &mut PlayerSocket) {
+    let gamemode = socket
+        .server
+        .read()
+        .unwrap()
+        .spawn_location
+        .world
+        .upgrade()
+        .expect("Spawn world does not exist")
+        .gamemode;
+
+    socket.send(clientbound::Abilities {
+        flags: abilities(gamemode),
+        flying_speed: 0.05,
+        fov_modifier: 0.1,
+    });
+}
+
+/// Ability flags: invulnerable (0x01), flying (0x02), allow flying (0x04) and instant break (0x08).
+fn abilities(gamemode: Gamemode) -> i8 {
+    match gamemode {
+        Gamemode::Survival | Gamemode::Adventure => 0,
+        Gamemode::Creative => 0x01 | 0x04 | 0x08,
+        Gamemode::Spectator => 0x01 | 0x02 | 0x04,
+    }
+}
+
+/// Moves the client to where the server has the player. Movement from the client is ignored
+/// until it confirms the teleport.
+fn send_position_and_look(socket: &mut PlayerSocket, uuid: &Uuid) {
+    let location = match with_player(socket, uuid, |player| player.location.clone()) {
+        Some(location) => location,
+        None => return,
     };
 
-    data.append(&mut codec::encode_byte(abilities));
-    data.append(&mut codec::encode_float(0.05)); // Flying speed
-    data.append(&mut codec::encode_float(0.1)); // FOV modifier
+    let teleport_id = rand::random();
+    socket.pending_teleport = Some(teleport_id);
 
-    super::send_packet(stream, 0x2E, &data)
+    socket.send(clientbound::PosLook {
+        x: location.x,
+        y: location.y,
+        z: location.z,
+        yaw: location.yaw,
+        pitch: location.pitch,
+        flags: 0, // Absolute
+        teleport_id: VarInt(teleport_id),
+    });
 }
 
-fn send_player_position_and_look(stream: &mut TcpStream, server: &crate::Obelisk) -> Result<(), Error> {
-    let mut data = Vec::new();
+pub fn send_keep_alive(socket: &mut PlayerSocket, id: i64) {
+    socket.send(clientbound::KeepAlive { id });
+}
 
-    let vec = server.spawn_location.to_vector();
-    data.append(&mut codec::encode_double(vec.x));
-    data.append(&mut codec::encode_double(vec.y));
-    data.append(&mut codec::encode_double(vec.z));
-    data.append(&mut codec::encode_float(0.0)); // Yaw
-    data.append(&mut codec::encode_float(0.0)); // Pitch
-    data.append(&mut codec::encode_byte(0)); // Flags
-    data.append(&mut codec::encode_varint(rand::random())); // teleport id
+pub fn disconnect(reason: &str) -> clientbound::Disconnect {
+    let chat = json!({
+        "text": reason
+    })
+    .to_string();
 
-    super::send_packet(stream, 0x32, &data)
+    clientbound::Disconnect { reason: chat }
 }
-*/
@@ -0,0 +1,269 @@
+use bytes::BytesMut;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{Error, ErrorKind};
+use tokio::net::TcpStream;
+use tokio::prelude::*;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// Longest possible v1 header, including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Reads the PROXY protocol header a load balancer sends ahead of the client's own data.
+pub struct ReadHeader {
+    socket: Option<TcpStream>,
+    buf: BytesMut,
+}
+
+pub fn read_header(socket: TcpStream) -> ReadHeader {
+    ReadHeader {
+        socket: Some(socket),
+        buf: BytesMut::new(),
+    }
+}
+
+impl Future for ReadHeader {
+    /// The socket, the client's address if the proxy sent one, and whatever was read past the
+    /// end of the header.
+    type Item = (TcpStream, Option<SocketAddr>, BytesMut);
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        loop {
+            if let Some((length, source)) = parse(&self.buf)? {
+                self.buf.advance(length);
+                let socket = self
+                    .socket
+                    .take()
+                    .expect("ReadHeader polled after completion");
+                return Ok(Async::Ready((socket, source, self.buf.take())));
+            }
+
+            self.buf.reserve(512);
+            let socket = self
+                .socket
+                .as_mut()
+                .expect("ReadHeader polled after completion");
+            if futures::try_ready!(AsyncRead::read_buf(socket, &mut self.buf)) == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Connection closed before sending a PROXY header",
+                ));
+            }
+        }
+    }
+}
+
+/// Parses the header at the start of `buf` into its length and source address, or `None` if
+/// more data is needed.
+fn parse(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+    if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if V2_SIGNATURE.starts_with(buf) || V1_PREFIX.starts_with(buf) {
+        Ok(None)
+    } else {
+        Err(invalid("Missing PROXY protocol header"))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+    let end = match buf.windows(2).position(|pair| pair == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() >= V1_MAX_LENGTH => return Err(invalid("PROXY header too long")),
+        None => return Ok(None),
+    };
+
+    if end + 2 > V1_MAX_LENGTH {
+        return Err(invalid("PROXY header too long"));
+    }
+
+    let line = std::str::from_utf8(&buf[..end]).map_err(|_| invalid("Malformed PROXY header"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    let source = match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4", source, _, port, _] => {
+            let ip: Ipv4Addr = source
+                .parse()
+                .map_err(|_| invalid("Malformed PROXY header"))?;
+            Some(SocketAddr::new(IpAddr::V4(ip), parse_port(port)?))
+        }
+        ["PROXY", "TCP6", source, _, port, _] => {
+            let ip: Ipv6Addr = source
+                .parse()
+                .map_err(|_| invalid("Malformed PROXY header"))?;
+            Some(SocketAddr::new(IpAddr::V6(ip), parse_port(port)?))
+        }
+        _ => return Err(invalid("Malformed PROXY header")),
+    };
+
+    Ok(Some((end + 2, source)))
+}
+
+fn parse_port(port: &str) -> Result<u16, Error> {
+    port.parse().map_err(|_| invalid("Malformed PROXY header"))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+    if buf.len() < V2_HEADER_LENGTH {
+        return Ok(None);
+    }
+
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0F;
+    let family = buf[13] >> 4;
+    let length = V2_HEADER_LENGTH + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+
+    if version != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+
+    if buf.len() < length {
+        return Ok(None);
+    }
+
+    let addresses = &buf[V2_HEADER_LENGTH..length];
+    let source = match (command, family) {
+        // LOCAL connections come from the proxy itself, such as health checks
+        (0x0, _) => None,
+        (0x1, 0x1) => {
+            if addresses.len() < 12 {
+                return Err(invalid("PROXY header addresses too short"));
+            }
+
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&addresses[..4]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        (0x1, 0x2) => {
+            if addresses.len() < 36 {
+                return Err(invalid("PROXY header addresses too short"));
+            }
+
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        // Unspecified and unix socket addresses don't say anything useful about the client
+        (0x1, _) => None,
+        _ => return Err(invalid("Unsupported PROXY command")),
+    };
+
+    Ok(Some((length, source)))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family << 4 | 0x1); // Over TCP
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test]
+    fn v1_headers_give_the_source_address() {
+        let header = b"PROXY TCP4 203.0.113.7 192.0.2.1 51234 25565\r\n\x10\x00";
+        assert_eq!(
+            parse(header).unwrap(),
+            Some((header.len() - 2, Some("203.0.113.7:51234".parse().unwrap())))
+        );
+
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 25565\r\n";
+        assert_eq!(
+            parse(header).unwrap(),
+            Some((header.len(), Some("[2001:db8::1]:4000".parse().unwrap())))
+        );
+
+        let header = b"PROXY UNKNOWN\r\n";
+        assert_eq!(parse(header).unwrap(), Some((header.len(), None)));
+    }
+
+    #[test]
+    fn v1_headers_wait_for_their_end() {
+        assert_eq!(parse(b"PRO").unwrap(), None);
+        assert_eq!(parse(b"PROXY TCP4 203.0.113.7 192.0").unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_v1_headers_are_rejected() {
+        assert!(parse(b"PROXY TCP4 not.an.ip 192.0.2.1 1 2\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 203.0.113.7 192.0.2.1 70000 25565\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 203.0.113.7\r\n").is_err());
+
+        let mut long = b"PROXY ".to_vec();
+        long.resize(V1_MAX_LENGTH, b'1');
+        assert!(parse(&long).is_err());
+    }
+
+    #[test]
+    fn v2_headers_give_the_source_address() {
+        let mut addresses = vec![203, 0, 113, 7, 192, 0, 2, 1];
+        addresses.extend_from_slice(&51234u16.to_be_bytes());
+        addresses.extend_from_slice(&25565u16.to_be_bytes());
+        let mut header = v2(0x1, 0x1, &addresses);
+        let length = header.len();
+        header.extend_from_slice(b"\x10\x00");
+
+        assert_eq!(
+            parse(&header).unwrap(),
+            Some((length, Some("203.0.113.7:51234".parse().unwrap())))
+        );
+
+        let mut addresses = vec![0; 36];
+        addresses[0] = 0x20;
+        addresses[1] = 0x01;
+        addresses[15] = 0x01;
+        addresses[32..34].copy_from_slice(&4000u16.to_be_bytes());
+        let header = v2(0x1, 0x2, &addresses);
+        assert_eq!(
+            parse(&header).unwrap(),
+            Some((header.len(), Some("[2001::1]:4000".parse().unwrap())))
+        );
+    }
+
+    #[test]
+    fn v2_local_and_unspecified_headers_have_no_source() {
+        let header = v2(0x0, 0x0, &[]);
+        assert_eq!(parse(&header).unwrap(), Some((header.len(), None)));
+
+        let header = v2(0x1, 0x3, &[0; 216]);
+        assert_eq!(parse(&header).unwrap(), Some((header.len(), None)));
+    }
+
+    #[test]
+    fn v2_headers_wait_for_their_addresses() {
+        let header = v2(0x1, 0x1, &[0; 12]);
+        assert_eq!(parse(&header[..5]).unwrap(), None);
+        assert_eq!(parse(&header[..V2_HEADER_LENGTH + 4]).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_v2_headers_are_rejected() {
+        assert!(parse(&v2(0x1, 0x1, &[0; 4])).is_err());
+        assert!(parse(&v2(0x1, 0x2, &[0; 12])).is_err());
+        assert!(parse(&v2(0x2, 0x1, &[0; 12])).is_err());
+
+        let mut header = v2(0x1, 0x1, &[0; 12]);
+        header[12] = 0x11;
+        assert!(parse(&header).is_err());
+    }
+
+    #[test]
+    fn connections_without_a_header_are_rejected() {
+        assert!(parse(b"\x10\x00\xFB\x03").is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use super::status;
+use crate::Obelisk;
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::Error;
+use tokio::net::UdpSocket;
+use tokio::prelude::*;
+use tokio::reactor::Handle;
+
+const MAGIC: u16 = 0xFEFD;
+const HANDSHAKE: u8 = 9;
+const STAT: u8 = 0;
+// Full stat requests pad the challenge token out with four more bytes
+const BASIC_STAT_LENGTH: usize = 11;
+const FULL_STAT_LENGTH: usize = 15;
+// Vanilla hands out a new challenge token every 30 seconds
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+// Fixed padding the full stat response has around its sections
+const KEY_VALUE_PADDING: &[u8] = b"splitnum\0\x80\0";
+const PLAYERS_PADDING: &[u8] = b"\x01player_\0\0";
+// Anything longer than a full stat request is cut short and then ignored
+const MAX_REQUEST_SIZE: usize = 1024;
+// How many handshakes go by between clearing out challenge tokens that have expired
+const PRUNE_INTERVAL: u32 = 200;
+
+pub fn bind(address: &SocketAddr) -> Result<UdpSocket, Error> {
+    let socket = std::net::UdpSocket::bind(address).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("Unable to bind query on {}: {}", address, e),
+        )
+    })?;
+
+    UdpSocket::from_std(socket, &Handle::default())
+}
+
+/// Answers queries on `socket`. A request that can't be read or answered is logged and
+/// skipped rather than stopping the others.
+pub fn serve(server: &Obelisk, socket: UdpSocket) -> impl Future<Item = (), Error = ()> {
+    Query {
+        server: Server::new(server),
+        socket,
+        challenges: Challenges::default(),
+        buf: vec![0; MAX_REQUEST_SIZE],
+        reply: None,
+    }
+}
+
+struct Query {
+    server: Server,
+    socket: UdpSocket,
+    challenges: Challenges,
+    buf: Vec<u8>,
+    // Waiting for the socket to have room for it
+    reply: Option<(Bytes, SocketAddr)>,
+}
+
+impl Future for Query {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Some((reply, address)) = &self.reply {
+                match self.socket.poll_send_to(reply, address) {
+                    Ok(Async::Ready(_)) => (),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => println!("Unable to answer query from {}: {}", address, e),
+                }
+                self.reply = None;
+            }
+
+            match self.socket.poll_recv_from(&mut self.buf) {
+                Ok(Async::Ready((length, address))) => {
+                    let request = BytesMut::from(&self.buf[..length]);
+                    self.reply = respond(&self.server, &mut self.challenges, request, address)
+                        .map(|reply| (reply, address));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => println!("Unable to read query: {}", e),
+            }
+        }
+    }
+}
+
+/// What queries report about the server. Only the players change once it's started, and
+/// those come from the status, so answering a query never locks the server.
+struct Server {
+    status: Arc<status::Cache>,
+    // The name of the world players spawn in
+    map: String,
+    // Where clients connect to, the first address the server listens on
+    host: IpAddr,
+    port: u16,
+}
+
+impl Server {
+    fn new(server: &Obelisk) -> Server {
+        let map = server
+            .spawn_location
+            .world
+            .upgrade()
+            .map(|world| world.name.clone())
+            .unwrap_or_default();
+        let (host, port) = match server.config.listen.first() {
+            Some(address) => (address.ip(), address.port()),
+            None => (IpAddr::from([0, 0, 0, 0]), 0),
+        };
+
+        Server {
+            status: server.status.clone(),
+            map,
+            host,
+            port,
+        }
+    }
+}
+
+/// Challenge tokens handed out by address, which later stat requests have to echo.
+#[derive(Default)]
+struct Challenges {
+    tokens: HashMap<SocketAddr, (i32, Instant)>,
+    issued_since_prune: u32,
+}
+
+impl Challenges {
+    fn issue(&mut self, address: SocketAddr) -> i32 {
+        let now = Instant::now();
+
+        self.issued_since_prune += 1;
+        if self.issued_since_prune >= PRUNE_INTERVAL {
+            self.issued_since_prune = 0;
+            self.tokens
+                .retain(|_, (_, issued)| now.duration_since(*issued) < CHALLENGE_LIFETIME);
+        }
+
+        let token = rand::random();
+        self.tokens.insert(address, (token, now));
+        token
+    }
+
+    fn check(&self, address: &SocketAddr, token: i32) -> bool {
+        match self.tokens.get(address) {
+            Some((issued_token, issued)) => {
+                *issued_token == token && issued.elapsed() < CHALLENGE_LIFETIME
+            }
+            None => false,
+        }
+    }
+}
+
+/// Builds the reply to a request, or returns `None` for anything that shouldn't be answered.
+fn respond(
+    server: &Server,
+    challenges: &mut Challenges,
+    request: BytesMut,
+    address: SocketAddr,
+) -> Option<Bytes> {
+    let length = request.len();
+    let mut buf = request.freeze().into_buf();
+    if length < 7 || buf.get_u16_be() != MAGIC {
+        return None;
+    }
+
+    let kind = buf.get_u8();
+    let session = buf.get_i32_be();
+
+    let mut reply = Vec::new();
+    reply.put_u8(kind);
+    reply.put_i32_be(session);
+
+    match kind {
+        HANDSHAKE => {
+            let token = challenges.issue(address);
+            put_string(&mut reply, &token.to_string());
+        }
+        STAT if length == BASIC_STAT_LENGTH || length == FULL_STAT_LENGTH => {
+            if !challenges.check(&address, buf.get_i32_be()) {
+                return None;
+            }
+
+            if length == BASIC_STAT_LENGTH {
+                put_basic_stat(&mut reply, server);
+            } else {
+                put_full_stat(&mut reply, server);
+            }
+        }
+        _ => return None,
+    }
+
+    Some(Bytes::from(reply))
+}
+
+fn put_basic_stat(reply: &mut Vec<u8>, server: &Server) {
+    let (online, max) = server.status.counts();
+
+    put_string(reply, server.status.motd());
+    put_string(reply, "SMP");
+    put_string(reply, &server.map);
+    put_string(reply, &online.to_string());
+    put_string(reply, &max.to_string());
+    reply.put_u16_le(server.port);
+    put_string(reply, &server.host.to_string());
+}
+
+fn put_full_stat(reply: &mut Vec<u8>, server: &Server) {
+    let usernames = server.status.usernames();
+    let (_, max) = server.status.counts();
+    let fields = [
+        ("hostname", server.status.motd().to_string()),
+        ("gametype", String::from("SMP")),
+        ("game_id", String::from("MINECRAFT")),
+        ("version", status::version_name()),
+        ("plugins", String::new()),
+        ("map", server.map.clone()),
+        ("numplayers", usernames.len().to_string()),
+        ("maxplayers", max.to_string()),
+        ("hostport", server.port.to_string()),
+        ("hostip", server.host.to_string()),
+    ];
+
+    reply.put_slice(KEY_VALUE_PADDING);
+    for (key, value) in fields.iter() {
+        put_string(reply, key);
+        put_string(reply, value);
+    }
+    reply.put_u8(0);
+
+    reply.put_slice(PLAYERS_PADDING);
+    for username in &usernames {
+        put_string(reply, username);
+    }
+    reply.put_u8(0);
+}
+
+// Strings are null terminated rather than length prefixed
+fn put_string(reply: &mut Vec<u8>, string: &str) {
+    reply.put_slice(string.as_bytes());
+    reply.put_u8(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::net::output::OutputQueue;
+    use crate::net::session::tests::NobodyJoined;
+    use uuid::Uuid;
+
+    fn address() -> SocketAddr {
+        "203.0.113.7:40000".parse().unwrap()
+    }
+
+    fn obelisk() -> Obelisk {
+        let config = Config {
+            listen: vec!["192.0.2.1:25570".parse().unwrap()],
+            ..Config::for_tests()
+        };
+
+        Obelisk::new(config, Arc::new(NobodyJoined))
+    }
+
+    fn server() -> Server {
+        Server::new(&obelisk())
+    }
+
+    fn request(kind: u8, session: i32, payload: &[u8]) -> BytesMut {
+        let mut request = Vec::new();
+        request.put_u16_be(MAGIC);
+        request.put_u8(kind);
+        request.put_i32_be(session);
+        request.extend_from_slice(payload);
+        BytesMut::from(request)
+    }
+
+    // Shakes hands as `address`, returning the challenge token handed out
+    fn handshake(server: &Server, challenges: &mut Challenges) -> i32 {
+        let reply = respond(server, challenges, request(HANDSHAKE, 1, &[]), address()).unwrap();
+        assert_eq!(&reply[..5], &[HANDSHAKE, 0, 0, 0, 1]);
+
+        let token = std::str::from_utf8(&reply[5..reply.len() - 1]).unwrap();
+        token.parse().unwrap()
+    }
+
+    fn strings(data: &[u8]) -> Vec<&str> {
+        data.split(|b| *b == 0)
+            .map(|s| std::str::from_utf8(s).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn basic_stat_needs_the_challenge_token() {
+        let server = server();
+        let mut challenges = Challenges::default();
+        let token = handshake(&server, &mut challenges);
+
+        let wrong = request(STAT, 2, &token.wrapping_add(1).to_be_bytes());
+        assert!(respond(&server, &mut challenges, wrong, address()).is_none());
+
+        let elsewhere = "203.0.113.8:40000".parse().unwrap();
+        let stat = request(STAT, 2, &token.to_be_bytes());
+        assert!(respond(&server, &mut challenges, stat.clone(), elsewhere).is_none());
+
+        let reply = respond(&server, &mut challenges, stat, address()).unwrap();
+        assert_eq!(&reply[..5], &[STAT, 0, 0, 0, 2]);
+
+        // Port comes between the player counts and the host, as a little endian short
+        let fields = &reply[5..];
+        let port = fields.len() - b"192.0.2.1\0".len() - 2;
+        assert_eq!(
+            strings(&fields[..port - 1]),
+            vec!["An Obelisk server", "SMP", "world", "0", "10"]
+        );
+        assert_eq!(&fields[port..port + 2], &25570u16.to_le_bytes());
+        assert_eq!(&fields[port + 2..], b"192.0.2.1\0");
+    }
+
+    #[test]
+    fn full_stat_lists_keys_and_players() {
+        let mut obelisk = obelisk();
+        let server = Server::new(&obelisk);
+        // Joins after the query started, which only hears about it through the status
+        obelisk.create_player(
+            Uuid::nil(),
+            String::from("Notch"),
+            address(),
+            Vec::new(),
+            OutputQueue::new(1024).stats(),
+        );
+        let mut challenges = Challenges::default();
+        let token = handshake(&server, &mut challenges);
+
+        let mut payload = token.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0; 4]);
+        let reply = respond(
+            &server,
+            &mut challenges,
+            request(STAT, 3, &payload),
+            address(),
+        )
+        .unwrap();
+
+        let body = &reply[5..];
+        assert!(body.starts_with(KEY_VALUE_PADDING));
+        let players = body
+            .windows(PLAYERS_PADDING.len())
+            .position(|window| window == PLAYERS_PADDING)
+            .unwrap();
+
+        let fields = strings(&body[KEY_VALUE_PADDING.len()..players - 2]);
+        let pairs: HashMap<&str, &str> = fields.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        assert_eq!(pairs["hostname"], "An Obelisk server");
+        assert_eq!(pairs["numplayers"], "1");
+        assert_eq!(pairs["maxplayers"], "10");
+        assert_eq!(pairs["hostport"], "25570");
+        assert_eq!(&body[players + PLAYERS_PADDING.len()..], b"Notch\0\0");
+    }
+
+    #[test]
+    fn expired_challenges_are_cleared_out() {
+        let mut challenges = Challenges::default();
+        let expired = "203.0.113.8:40000".parse().unwrap();
+        let issued = Instant::now() - CHALLENGE_LIFETIME;
+        challenges.tokens.insert(expired, (1, issued));
+
+        // Only every so often, rather than on every handshake
+        for _ in 1..PRUNE_INTERVAL {
+            challenges.issue(address());
+        }
+        assert!(challenges.tokens.contains_key(&expired));
+
+        challenges.issue(address());
+        assert!(!challenges.tokens.contains_key(&expired));
+        assert!(challenges.tokens.contains_key(&address()));
+    }
+
+    #[test]
+    fn other_datagrams_are_ignored() {
+        let server = server();
+        let mut challenges = Challenges::default();
+
+        let garbage = BytesMut::from(&b"\xfe\xfc\x09\0\0\0\x01"[..]);
+        assert!(respond(&server, &mut challenges, garbage, address()).is_none());
+        let short = BytesMut::from(&b"\xfe\xfd\x09"[..]);
+        assert!(respond(&server, &mut challenges, short, address()).is_none());
+        let unknown = request(0x42, 1, &[]);
+        assert!(respond(&server, &mut challenges, unknown, address()).is_none());
+        let stat_without_token = request(STAT, 1, &[]);
+        assert!(respond(&server, &mut challenges, stat_without_token, address()).is_none());
+    }
+}
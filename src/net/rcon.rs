@@ -0,0 +1,275 @@
+use crate::commands;
+use crate::Obelisk;
+use bytes::{Buf, BufMut, BytesMut, IntoBuf};
+use std::sync::{Arc, RwLock};
+use tokio::codec::{Decoder, Encoder, Framed};
+use tokio::io::{Error, ErrorKind};
+use tokio::net::TcpStream;
+use tokio::prelude::*;
+
+const RESPONSE: i32 = 0;
+const COMMAND: i32 = 2;
+const AUTH_RESPONSE: i32 = 2;
+const LOGIN: i32 = 3;
+// Sent back in place of the request id when a login fails or a command isn't authenticated
+const AUTH_FAILED: i32 = -1;
+// Longest packet vanilla accepts from a client
+const MAX_PACKET_LENGTH: usize = 1460;
+// Responses longer than this are split over several packets
+const MAX_RESPONSE_PAYLOAD: usize = 4096;
+
+/// An RCON packet. Unlike the game protocol everything is little endian.
+struct Packet {
+    id: i32,
+    kind: i32,
+    payload: String,
+}
+
+/// Handles one RCON connection until it closes.
+pub fn accept(server: Arc<RwLock<Obelisk>>, password: String, socket: TcpStream) {
+    let peer = match socket.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            println!("RCON connection error: {:?}", e);
+            return;
+        }
+    };
+
+    let shutdown = server.read().unwrap().shutdown.clone();
+    let (responses, requests) = Framed::new(socket, RconCodec).split();
+    let mut authenticated = false;
+
+    let replies = requests
+        .map(move |request| {
+            let replies = respond(&server, &password, &mut authenticated, request);
+            stream::iter_ok::<_, Error>(replies)
+        })
+        .flatten();
+
+    // A reply to `stop` is written out before the connection notices and closes
+    tokio::spawn(
+        shutdown.until(
+            responses
+                .send_all(replies)
+                .map(|_| ())
+                .map_err(move |e| println!("RCON error from {}: {}", peer, e)),
+        ),
+    );
+}
+
+fn respond(
+    server: &RwLock<Obelisk>,
+    password: &str,
+    authenticated: &mut bool,
+    request: Packet,
+) -> Vec<Packet> {
+    match request.kind {
+        LOGIN => {
+            *authenticated = request.payload == password;
+            let id = if *authenticated {
+                request.id
+            } else {
+                AUTH_FAILED
+            };
+
+            vec![Packet {
+                id,
+                kind: AUTH_RESPONSE,
+                payload: String::new(),
+            }]
+        }
+        COMMAND if *authenticated => {
+            let output = commands::run(server, &request.payload);
+            split_response(request.id, &output)
+        }
+        COMMAND => vec![Packet {
+            id: AUTH_FAILED,
+            kind: AUTH_RESPONSE,
+            payload: String::new(),
+        }],
+        kind => split_response(request.id, &format!("Unknown request {:x}", kind)),
+    }
+}
+
+/// Splits a command's output over as many response packets as it takes, without breaking up
+/// any characters.
+fn split_response(id: i32, output: &str) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut rest = output;
+
+    loop {
+        let mut end = rest.len().min(MAX_RESPONSE_PAYLOAD);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (payload, remaining) = rest.split_at(end);
+        packets.push(Packet {
+            id,
+            kind: RESPONSE,
+            payload: payload.to_string(),
+        });
+
+        if remaining.is_empty() {
+            return packets;
+        }
+        rest = remaining;
+    }
+}
+
+/// Frames packets with their length. After the id and type, the payload is null terminated and
+/// followed by one more null byte.
+struct RconCodec;
+
+impl Decoder for RconCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+
+        let length = (&src[..4]).into_buf().get_i32_le();
+        if length < 10 || length as usize > MAX_PACKET_LENGTH {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid RCON packet length {}", length),
+            ));
+        }
+
+        let length = length as usize;
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let packet = src.split_to(length);
+        let mut header = (&packet[..8]).into_buf();
+        let id = header.get_i32_le();
+        let kind = header.get_i32_le();
+
+        let body = &packet[8..];
+        let payload = match body.iter().position(|b| *b == 0) {
+            Some(end) => String::from_utf8_lossy(&body[..end]).into_owned(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "RCON payload wasn't null terminated",
+                ))
+            }
+        };
+
+        Ok(Some(Packet { id, kind, payload }))
+    }
+}
+
+impl Encoder for RconCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let length = 4 + 4 + item.payload.len() + 2;
+        dst.reserve(4 + length);
+
+        dst.put_i32_le(length as i32);
+        dst.put_i32_le(item.id);
+        dst.put_i32_le(item.kind);
+        dst.put_slice(item.payload.as_bytes());
+        dst.put_slice(&[0, 0]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::net::session::tests::NobodyJoined;
+
+    fn encode(packet: Packet) -> BytesMut {
+        let mut dst = BytesMut::new();
+        RconCodec.encode(packet, &mut dst).unwrap();
+        dst
+    }
+
+    fn request(id: i32, kind: i32, payload: &str) -> Packet {
+        Packet {
+            id,
+            kind,
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn packets_are_little_endian_and_null_terminated() {
+        let frame = encode(request(7, COMMAND, "list"));
+        assert_eq!(&frame[..], b"\x0e\0\0\0\x07\0\0\0\x02\0\0\0list\0\0");
+
+        let mut src = frame;
+        let packet = RconCodec.decode(&mut src).unwrap().unwrap();
+        assert_eq!((packet.id, packet.kind), (7, COMMAND));
+        assert_eq!(packet.payload, "list");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn partial_packets_wait_for_the_rest() {
+        let mut src = encode(request(1, LOGIN, "password"));
+        let rest = src.split_off(9);
+
+        assert!(RconCodec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&rest);
+        assert!(RconCodec.decode(&mut src).unwrap().is_some());
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        let mut too_short = BytesMut::from(&b"\x09\0\0\0\0\0\0\0\0\0\0\0\0"[..]);
+        assert!(RconCodec.decode(&mut too_short).is_err());
+
+        let mut too_long = BytesMut::from(&b"\xb5\x05\0\0"[..]);
+        assert!(RconCodec.decode(&mut too_long).is_err());
+
+        let mut unterminated = BytesMut::from(&b"\x0a\0\0\0\x01\0\0\0\x02\0\0\0ab"[..]);
+        assert!(RconCodec.decode(&mut unterminated).is_err());
+    }
+
+    #[test]
+    fn long_responses_are_split_between_characters() {
+        let output = "é".repeat(MAX_RESPONSE_PAYLOAD);
+        let packets = split_response(3, &output);
+
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|packet| packet.id == 3));
+        assert!(packets[0].payload.len() <= MAX_RESPONSE_PAYLOAD);
+        let joined: String = packets.iter().map(|packet| &packet.payload[..]).collect();
+        assert_eq!(joined, output);
+
+        assert_eq!(split_response(4, "").len(), 1);
+    }
+
+    #[test]
+    fn commands_need_the_password_first() {
+        let server = RwLock::new(Obelisk::new(Config::for_tests(), Arc::new(NobodyJoined)));
+        let mut authenticated = false;
+        let mut respond = |packet| respond(&server, "hunter2", &mut authenticated, packet);
+
+        let reply = respond(request(1, COMMAND, "list"));
+        assert_eq!((reply[0].id, reply[0].kind), (AUTH_FAILED, AUTH_RESPONSE));
+
+        let reply = respond(request(2, LOGIN, "hunter1"));
+        assert_eq!((reply[0].id, reply[0].kind), (AUTH_FAILED, AUTH_RESPONSE));
+
+        let reply = respond(request(3, LOGIN, "hunter2"));
+        assert_eq!((reply[0].id, reply[0].kind), (3, AUTH_RESPONSE));
+
+        let reply = respond(request(4, COMMAND, "list"));
+        assert_eq!((reply[0].id, reply[0].kind), (4, RESPONSE));
+        assert!(reply[0]
+            .payload
+            .starts_with("There are 0 of a max 10 players online"));
+    }
+}
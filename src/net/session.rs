@@ -0,0 +1,244 @@
+use crate::entities::player::ProfileProperty;
+use futures::{Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Client, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::prelude::FutureExt;
+use uuid::Uuid;
+
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+// The client waits on the login screen the whole time, so it can't be long
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct GameProfile {
+    pub id: Uuid,
+    pub name: String,
+    pub properties: Vec<ProfileProperty>,
+}
+
+pub type Verification = Box<dyn Future<Item = Option<GameProfile>, Error = Error> + Send>;
+
+/// Checks that a client logging in has joined the server through the session server.
+pub trait SessionVerifier: Send + Sync {
+    /// Resolves to the player's profile, or `None` if the session server doesn't know about them.
+    /// Fails if the session server can't be asked.
+    fn has_joined(&self, username: &str, server_hash: &str) -> Verification;
+}
+
+/// Verifies sessions against a Yggdrasil-compatible session server over HTTP(S).
+pub struct HttpSessionVerifier {
+    client: Client<HttpsConnector<HttpConnector>>,
+    base_url: String,
+    timeout: Duration,
+}
+
+impl HttpSessionVerifier {
+    pub fn new(base_url: &str) -> Result<HttpSessionVerifier, Error> {
+        let connector = HttpsConnector::new(4).map_err(|e| Error::other(e.to_string()))?;
+
+        Ok(HttpSessionVerifier {
+            client: Client::builder().build(connector),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// How long to wait for the session server to answer before failing the login.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn mojang() -> Result<HttpSessionVerifier, Error> {
+        HttpSessionVerifier::new(MOJANG_SESSION_SERVER)
+    }
+}
+
+impl SessionVerifier for HttpSessionVerifier {
+    fn has_joined(&self, username: &str, server_hash: &str) -> Verification {
+        #[derive(Deserialize)]
+        struct Profile {
+            id: String,
+            name: String,
+            #[serde(default)]
+            properties: Vec<ProfileProperty>,
+        }
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("username", username)
+            .append_pair("serverId", server_hash)
+            .finish();
+        let url = format!("{}/session/minecraft/hasJoined?{}", self.base_url, query);
+
+        let uri = match url.parse() {
+            Ok(uri) => uri,
+            Err(_) => {
+                return Box::new(futures::future::err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Invalid session server url",
+                )))
+            }
+        };
+
+        let verification = self
+            .client
+            .get(uri)
+            .and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map(move |body| (status, body))
+            })
+            .map_err(Error::other)
+            .timeout(self.timeout)
+            .map_err(|e| match e.into_inner() {
+                Some(e) => e,
+                None => Error::new(ErrorKind::TimedOut, "Session server didn't answer in time"),
+            })
+            .and_then(|(status, body)| {
+                // No content is how the session server says the player hasn't joined
+                match status {
+                    StatusCode::OK => (),
+                    StatusCode::NO_CONTENT => return Ok(None),
+                    status => {
+                        return Err(Error::other(format!(
+                            "Session server answered with {}",
+                            status
+                        )))
+                    }
+                }
+
+                let profile: Profile = serde_json::from_slice(&body)?;
+                let id = Uuid::parse_str(&profile.id)
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid profile id"))?;
+
+                Ok(Some(GameProfile {
+                    id,
+                    name: profile.name,
+                    properties: profile.properties,
+                }))
+            });
+
+        Box::new(verification)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+    use tokio::runtime::Runtime;
+
+    const PROFILE: &str = r#"{
+        "id": "069a79f444e94726a5befca90e38aaf5",
+        "name": "Notch",
+        "properties": [{"name": "textures", "value": "abc", "signature": "def"}]
+    }"#;
+
+    /// A session server nobody has joined through, for tests that never get as far as asking it.
+    pub struct NobodyJoined;
+
+    impl SessionVerifier for NobodyJoined {
+        fn has_joined(&self, _username: &str, _server_hash: &str) -> Verification {
+            Box::new(futures::future::ok(None))
+        }
+    }
+
+    /// A session server on its own thread that answers one request with `status` and `body`,
+    /// handing back the request line it got.
+    fn stub_server(status: &str, body: &str) -> (HttpSessionVerifier, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+
+        let request = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut header = String::new();
+            while header != "\r\n" {
+                header.clear();
+                reader.read_line(&mut header).unwrap();
+            }
+
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (HttpSessionVerifier::new(&url).unwrap(), request)
+    }
+
+    fn has_joined(
+        verifier: &HttpSessionVerifier,
+        username: &str,
+        server_hash: &str,
+    ) -> Result<Option<GameProfile>, Error> {
+        Runtime::new()
+            .unwrap()
+            .block_on(verifier.has_joined(username, server_hash))
+    }
+
+    #[test]
+    fn profiles_come_from_the_session_server() {
+        let (verifier, request) = stub_server("200 OK", PROFILE);
+        let profile = has_joined(&verifier, "Notch", "-7c9d5b00")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            request.join().unwrap(),
+            "GET /session/minecraft/hasJoined?username=Notch&serverId=-7c9d5b00 HTTP/1.1\r\n"
+        );
+        assert_eq!(
+            profile.id.to_simple().to_string(),
+            "069a79f444e94726a5befca90e38aaf5"
+        );
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.properties.len(), 1);
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("def"));
+    }
+
+    #[test]
+    fn no_content_means_the_player_has_not_joined() {
+        let (verifier, _) = stub_server("204 No Content", "");
+        assert!(has_joined(&verifier, "Notch", "1234").unwrap().is_none());
+    }
+
+    #[test]
+    fn error_statuses_fail_the_check() {
+        for status in &[
+            "403 Forbidden",
+            "429 Too Many Requests",
+            "500 Internal Server Error",
+        ] {
+            let (verifier, _) = stub_server(status, "{}");
+            assert!(has_joined(&verifier, "Notch", "1234").is_err());
+        }
+
+        let (verifier, _) = stub_server("200 OK", r#"{"id": "not a uuid", "name": "Notch"}"#);
+        assert!(has_joined(&verifier, "Notch", "1234").is_err());
+    }
+
+    #[test]
+    fn session_servers_that_stay_quiet_time_out() {
+        // Connections are left waiting in the backlog, never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut verifier = HttpSessionVerifier::new(&url).unwrap();
+        verifier.set_timeout(Duration::from_millis(200));
+
+        let e = has_joined(&verifier, "Notch", "1234").err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,91 @@
+use futures::future::Shared;
+use futures::sync::{mpsc, oneshot};
+use std::sync::{Arc, Mutex};
+use tokio::prelude::*;
+
+/// Stops the server. Clones are handles on the same shutdown, so any of them can start it.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    stopping: Shared<oneshot::Receiver<()>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        let (sender, receiver) = oneshot::channel();
+        Shutdown {
+            sender: Arc::new(Mutex::new(Some(sender))),
+            stopping: receiver.shared(),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Starts shutting the server down. Returns false if it already was.
+    pub fn stop(&self) -> bool {
+        match self.sender.lock().unwrap().take() {
+            Some(sender) => {
+                let _ = sender.send(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the server is shutting down. Inside a task, the task is woken once it starts.
+    pub fn poll_stopping(&mut self) -> bool {
+        match self.stopping.poll() {
+            Ok(Async::NotReady) => false,
+            // The sender lives as long as any handle, so it can't have been dropped unused
+            Ok(Async::Ready(_)) | Err(_) => true,
+        }
+    }
+
+    /// Resolves once the server starts shutting down.
+    pub fn wait(&self) -> impl Future<Item = (), Error = ()> {
+        self.stopping.clone().then(|_| Ok(()))
+    }
+
+    /// Runs `future` until it finishes or the server starts shutting down, whichever is first.
+    pub fn until<F>(&self, future: F) -> impl Future<Item = (), Error = ()>
+    where
+        F: Future<Item = (), Error = ()>,
+    {
+        future.select(self.wait()).then(|_| Ok(()))
+    }
+}
+
+/// Keeps track of open connections so shutdown can wait for them to close.
+pub struct Drain {
+    sender: mpsc::Sender<()>,
+    receiver: mpsc::Receiver<()>,
+}
+
+/// Counts as an open connection until dropped. Clones count separately.
+#[derive(Clone)]
+pub struct Running {
+    _sender: mpsc::Sender<()>,
+}
+
+impl Default for Drain {
+    fn default() -> Drain {
+        let (sender, receiver) = mpsc::channel(0);
+        Drain { sender, receiver }
+    }
+}
+
+impl Drain {
+    pub fn track(&self) -> Running {
+        Running {
+            _sender: self.sender.clone(),
+        }
+    }
+
+    /// Resolves once everything tracked has been dropped.
+    pub fn wait(self) -> impl Future<Item = (), Error = ()> {
+        // Nothing is ever sent, the stream just ends when the last sender goes
+        let Drain { sender, receiver } = self;
+        drop(sender);
+        receiver.for_each(|_| Ok(()))
+    }
+}
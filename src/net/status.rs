@@ -1,69 +1,489 @@
-use super::codec;
-use super::{Packet, PlayerSocket};
+use super::packets::status::{clientbound, serverbound};
+use super::packets::ServerboundPacket;
+use super::version::Version as ProtocolVersion;
+use super::{codec, Packet, PlayerSocket};
+use crate::config::{Config, Motd, PlayerSample};
+use crate::entities::player::Player;
+use base64ct::{Base64, Encoding};
+use bytes::Bytes;
 use serde::Serialize;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
 use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
 
-pub fn handle_status(socket: &mut PlayerSocket, packet: &Packet) -> Result<(), Error> {
-    if packet.id == 0 {
-        send_status(socket);
-    } else if packet.id == 1 {
-        send_ping(socket, packet);
-    } else {
+const SAMPLE_SIZE: usize = 12;
+const FAVICON_SIZE: u32 = 64;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// Clients won't read a status response longer than a string can be, so the icon has to leave
+// room for everything else
+const MAX_FAVICON_LENGTH: usize = codec::MAX_STRING_LENGTH - 4096;
+// Sample entries that aren't players still need an id
+const NIL_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+#[derive(Serialize)]
+struct Status<'a> {
+    version: Version,
+    players: &'a Players,
+    description: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicon: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Version {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Serialize)]
+struct Players {
+    max: u32,
+    online: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sample: Vec<SamplePlayer>,
+}
+
+#[derive(Clone, Serialize)]
+struct SamplePlayer {
+    name: String,
+    id: String,
+}
+
+/// The status response, with what comes from config prepared once at startup. The server
+/// updates the players as they join and leave, so answering a ping never locks the server.
+pub struct Cache {
+    description: Value,
+    // The description as text, for legacy pings and queries
+    motd: String,
+    favicon: Option<String>,
+    sample: Sample,
+    current: Mutex<Current>,
+}
+
+enum Sample {
+    // Some of the players online
+    Online,
+    // The same lines whoever is online, or none at all
+    Fixed(Vec<SamplePlayer>),
+}
+
+struct Current {
+    players: Players,
+    // Everyone online, for full stat queries
+    usernames: Vec<String>,
+    // Serialized responses by the protocol version they echo, made the first time one is asked
+    // for after the players change
+    responses: HashMap<i32, String>,
+}
+
+impl Cache {
+    /// A status for nobody being online yet.
+    pub fn new(config: &Config, max_players: u32) -> Cache {
+        let (description, motd) = match config.motd {
+            Motd::Text(ref text) => (json!({ "text": text }), text.clone()),
+            Motd::Component(ref component) => {
+                let mut motd = String::new();
+                write_legacy(component, &Style::default(), &mut motd);
+                (component.clone(), motd)
+            }
+        };
+
+        let favicon = config.favicon.as_ref().and_then(|path| {
+            load_favicon(path)
+                .map_err(|e| println!("Not using {} as the icon: {}", path.display(), e))
+                .ok()
+                .flatten()
+        });
+
+        let sample = match config.player_sample {
+            PlayerSample::Online => Sample::Online,
+            PlayerSample::Hidden => Sample::Fixed(Vec::new()),
+            PlayerSample::Custom(ref lines) => Sample::Fixed(
+                lines
+                    .iter()
+                    .map(|line| SamplePlayer {
+                        name: line.clone(),
+                        id: String::from(NIL_UUID),
+                    })
+                    .collect(),
+            ),
+        };
+
+        let players = Players {
+            max: max_players,
+            online: 0,
+            sample: match sample {
+                Sample::Online => Vec::new(),
+                Sample::Fixed(ref sample) => sample.clone(),
+            },
+        };
+
+        Cache {
+            description,
+            motd,
+            favicon,
+            sample,
+            current: Mutex::new(Current {
+                players,
+                usernames: Vec::new(),
+                responses: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Takes the players now online into the status. Called whenever one joins or leaves.
+    pub fn update_players(&self, players: &HashMap<Uuid, Player>) {
+        let mut current = self.current.lock().unwrap();
+        current.players.online = players.len();
+        current.usernames = players
+            .values()
+            .map(|player| player.username.clone())
+            .collect();
+        if let Sample::Online = self.sample {
+            current.players.sample = players
+                .iter()
+                .take(SAMPLE_SIZE)
+                .map(|(uuid, player)| SamplePlayer {
+                    name: player.username.clone(),
+                    id: uuid.to_hyphenated().to_string(),
+                })
+                .collect();
+        }
+
+        current.responses.clear();
+    }
+
+    /// The status response as JSON, claiming to be the client's own version.
+    fn response(&self, protocol: i32) -> String {
+        let mut current = self.current.lock().unwrap();
+        let Current {
+            ref players,
+            ref mut responses,
+            ..
+        } = *current;
+
+        responses
+            .entry(protocol)
+            .or_insert_with(|| {
+                let status = Status {
+                    // Echoing a supported client's own version is what marks the server as
+                    // compatible
+                    version: Version {
+                        name: version_name(),
+                        protocol,
+                    },
+                    players,
+                    description: &self.description,
+                    favicon: self.favicon.as_deref(),
+                };
+
+                serde_json::to_string(&status).unwrap()
+            })
+            .clone()
+    }
+
+    /// How many players are online, and how many can be.
+    pub fn counts(&self) -> (usize, u32) {
+        let current = self.current.lock().unwrap();
+        (current.players.online, current.players.max)
+    }
+
+    /// The names of everyone online.
+    pub fn usernames(&self) -> Vec<String> {
+        self.current.lock().unwrap().usernames.clone()
+    }
+
+    /// The message of the day shown in server lists, as text.
+    pub fn motd(&self) -> &str {
+        &self.motd
+    }
+}
+
+/// Reads a PNG to use as the server's icon, as the data URL the status response takes. A
+/// missing file just means there's no icon.
+fn load_favicon(path: &Path) -> Result<Option<String>, Error> {
+    let png = match fs::read(path) {
+        Ok(png) => png,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // The image header always comes first, straight after the signature
+    if png.len() < 24 || &png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a PNG"));
+    }
+
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Icon is {}x{} but has to be {}x{}",
+                width, height, FAVICON_SIZE, FAVICON_SIZE
+            ),
+        ));
+    }
+
+    let favicon = format!("data:image/png;base64,{}", Base64::encode_string(&png));
+    if favicon.len() > MAX_FAVICON_LENGTH {
         return Err(Error::new(
             ErrorKind::InvalidData,
-            "Invalid status packet id",
+            format!("Icon is {} bytes, too large to send", png.len()),
         ));
     }
 
+    Ok(Some(favicon))
+}
+
+/// Formatting a chat component passes down to its children.
+#[derive(Clone, Default)]
+struct Style {
+    color: Option<char>,
+    // Formatting codes for bold, italic and so on
+    formats: String,
+}
+
+/// Flattens a chat component into text with `§` formatting codes.
+fn write_legacy(component: &Value, parent: &Style, out: &mut String) {
+    let object = match component {
+        Value::String(text) => {
+            out.push_str(text);
+            return;
+        }
+        Value::Array(components) => {
+            for component in components {
+                write_legacy(component, parent, out);
+            }
+            return;
+        }
+        Value::Object(object) => object,
+        _ => return,
+    };
+
+    let mut style = parent.clone();
+    if let Some(code) = object
+        .get("color")
+        .and_then(Value::as_str)
+        .and_then(color_code)
+    {
+        style.color = Some(code);
+    }
+    for (format, code) in FORMATS {
+        match object.get(*format).and_then(Value::as_bool) {
+            Some(true) if !style.formats.contains(*code) => style.formats.push(*code),
+            Some(false) => style.formats.retain(|c| c != *code),
+            _ => (),
+        }
+    }
+
+    if let Some(text) = object.get("text").and_then(Value::as_str) {
+        // Colors reset formatting, so they go first and everything's reset between parts
+        if !out.is_empty() {
+            out.push_str("§r");
+        }
+        if let Some(color) = style.color {
+            out.push('§');
+            out.push(color);
+        }
+        for code in style.formats.chars() {
+            out.push('§');
+            out.push(code);
+        }
+        out.push_str(text);
+    }
+
+    if let Some(extra) = object.get("extra") {
+        write_legacy(extra, &style, out);
+    }
+}
+
+const FORMATS: &[(&str, char)] = &[
+    ("obfuscated", 'k'),
+    ("bold", 'l'),
+    ("strikethrough", 'm'),
+    ("underlined", 'n'),
+    ("italic", 'o'),
+];
+
+fn color_code(color: &str) -> Option<char> {
+    let code = match color {
+        "black" => '0',
+        "dark_blue" => '1',
+        "dark_green" => '2',
+        "dark_aqua" => '3',
+        "dark_red" => '4',
+        "dark_purple" => '5',
+        "gold" => '6',
+        "gray" => '7',
+        "dark_gray" => '8',
+        "blue" => '9',
+        "green" => 'a',
+        "aqua" => 'b',
+        "red" => 'c',
+        "light_purple" => 'd',
+        "yellow" => 'e',
+        "white" => 'f',
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+// Pre-1.4 clients use § to separate fields, so they get the text without any formatting
+fn strip_codes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+pub fn handle_status(socket: &mut PlayerSocket, packet: &mut Packet) -> Result<(), Error> {
+    match packet.id {
+        serverbound::Request::ID => {
+            packet.read::<serverbound::Request>()?;
+            send_status(socket);
+        }
+        serverbound::Ping::ID => {
+            let ping: serverbound::Ping = packet.read()?;
+            send_pong(socket, ping.payload);
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid status packet id",
+            ))
+        }
+    }
+
     Ok(())
 }
 
-fn send_ping(socket: &mut PlayerSocket, packet: &Packet) {
-    let payload = packet.data.clone();
-    socket.send_packet(0x1, payload);
+fn send_pong(socket: &mut PlayerSocket, payload: i64) {
+    socket.send(clientbound::Pong { payload });
+}
+
+/// The range of versions clients can join with.
+pub fn version_name() -> String {
+    format!("{}-{}", ProtocolVersion::OLDEST.name(), crate::VERSION)
+}
+
+fn send_status(socket: &mut PlayerSocket) {
+    let json = socket.status.response(socket.version.protocol());
+    socket.send(clientbound::Response { json });
 }
 
-fn send_status<'a>(socket: &mut PlayerSocket) {
-    #[derive(Serialize)]
-    struct SamplePlayer<'a> {
-        name: &'a str,
-        id: String,
+/// Answers a pre-1.7 server list ping with a kick packet carrying the status as a string.
+pub fn send_legacy_status(socket: &mut PlayerSocket, packet: &Packet) {
+    let (online, max) = socket.status.counts();
+    let motd = &socket.status.motd;
+
+    let response = if packet.data.first() == Some(&1) {
+        // 1.4 to 1.6 clients follow the ping with 0x01 and understand the §1 format
+        format!(
+            "§1\0{}\0{}\0{}\0{}\0{}",
+            socket.version.protocol(),
+            version_name(),
+            motd,
+            online,
+            max
+        )
+    } else {
+        format!("{}§{}§{}", strip_codes(motd), online, max)
+    };
+
+    let chars: Vec<u16> = response.encode_utf16().collect();
+    let mut data = Vec::with_capacity(2 + chars.len() * 2);
+    codec::write_ushort(&mut data, chars.len() as u16);
+    for c in chars {
+        codec::write_ushort(&mut data, c);
     }
 
-    let response: Vec<u8>;
+    socket.send_packet(0xFF, Bytes::from(data));
+}
 
-    {
-        let server = socket.server.read().unwrap();
-
-        let player_sample: Vec<SamplePlayer> = server
-            .players
-            .iter()
-            .take(5)
-            .map(|(uuid, player)| SamplePlayer {
-                name: &player.username,
-                id: uuid.to_hyphenated().to_string(),
-            })
-            .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process;
 
-        response = codec::encode_string(
-            &json!({
-            "version": {
-                "name": "1.13.2",
-                "protocol": 404
-            },
-            "players": {
-                "max": server.max_players,
-                "online": server.players.len(),
-                "sample": &player_sample,
-            },
-            "description": {
-                "text": "Hello world"
-            }
-            })
-            .to_string(),
+    fn legacy(component: Value) -> String {
+        let mut motd = String::new();
+        write_legacy(&component, &Style::default(), &mut motd);
+        motd
+    }
+
+    // Just enough of a PNG for the header checks, padded out to `length` bytes
+    fn png(width: u32, height: u32, length: usize) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png.resize(length.max(png.len()), 0);
+        png
+    }
+
+    fn favicon(name: &str, contents: &[u8]) -> Result<Option<String>, Error> {
+        let path = temp_path(name);
+        fs::write(&path, contents).unwrap();
+        let favicon = load_favicon(&path);
+        fs::remove_file(&path).unwrap();
+        favicon
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("obelisk-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn components_flatten_to_formatting_codes() {
+        assert_eq!(legacy(json!("plain")), "plain");
+        assert_eq!(
+            legacy(json!({"text": "Hello", "color": "gold", "bold": true})),
+            "§6§lHello"
         );
+        assert_eq!(
+            legacy(json!({
+                "text": "A",
+                "color": "red",
+                "extra": [{"text": "B", "italic": true}, {"text": "C", "color": "blue"}]
+            })),
+            "§cA§r§c§oB§r§9C"
+        );
+        assert_eq!(
+            legacy(json!({"text": "", "bold": true, "extra": [{"text": "x", "bold": false}]})),
+            "§l§rx"
+        );
+        assert_eq!(legacy(json!({"text": "x", "color": "not a color"})), "x");
+    }
+
+    #[test]
+    fn pre_1_4_pings_get_plain_text() {
+        assert_eq!(strip_codes("§6§lHello§r world"), "Hello world");
+        assert_eq!(strip_codes("trailing §"), "trailing ");
     }
 
-    socket.send_packet(0x0, response);
+    #[test]
+    fn favicons_have_to_be_small_64_pixel_pngs() {
+        let icon = favicon("good.png", &png(64, 64, 100)).unwrap().unwrap();
+        assert!(icon.starts_with("data:image/png;base64,iVBORw0KGgo"));
+
+        assert!(favicon("text.png", b"not an image at all, just text").is_err());
+        assert!(favicon("large.png", &png(128, 128, 100)).is_err());
+        assert!(favicon("heavy.png", &png(64, 64, MAX_FAVICON_LENGTH)).is_err());
+        assert!(load_favicon(&temp_path("missing.png")).unwrap().is_none());
+    }
 }
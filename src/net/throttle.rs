@@ -0,0 +1,209 @@
+use crate::config::{Config, Forwarding};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How many logins go by between clearing out addresses that are no longer throttled
+const PRUNE_INTERVAL: u32 = 200;
+
+/// Tracks open connections and recent logins by address to enforce the configured limits.
+pub struct Limiter {
+    throttle: Option<Duration>,
+    max_per_ip: Option<usize>,
+    max_total: Option<usize>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    connections: HashMap<IpAddr, usize>,
+    total: usize,
+    last_login: HashMap<IpAddr, Instant>,
+    logins_since_prune: u32,
+}
+
+impl Limiter {
+    pub fn new(config: &Config) -> Limiter {
+        Limiter {
+            throttle: config.connection_throttle,
+            // Every player coming through a forwarding proxy shares its address
+            max_per_ip: match config.forwarding {
+                Forwarding::None => config.max_connections_per_ip,
+                Forwarding::BungeeCord | Forwarding::Velocity { .. } => None,
+            },
+            max_total: config.max_connections,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` if that would go over either cap.
+    /// The connection is counted until the returned guard is dropped.
+    pub fn connect(self: &Arc<Self>, ip: IpAddr) -> Option<Connection> {
+        let mut state = self.state.lock().unwrap();
+        let open = state.connections.get(&ip).cloned().unwrap_or(0);
+
+        if self.max_total.is_some_and(|max| state.total >= max)
+            || self.max_per_ip.is_some_and(|max| open >= max)
+        {
+            return None;
+        }
+
+        state.total += 1;
+        state.connections.insert(ip, open + 1);
+
+        Some(Connection {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    fn allow_login(&self, ip: IpAddr) -> bool {
+        let throttle = match self.throttle {
+            Some(throttle) => throttle,
+            None => return true,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        state.logins_since_prune += 1;
+        if state.logins_since_prune >= PRUNE_INTERVAL {
+            state.logins_since_prune = 0;
+            state
+                .last_login
+                .retain(|_, last| now.duration_since(*last) < throttle);
+        }
+
+        // Attempts made while throttled still count, so a client has to actually back off
+        match state.last_login.insert(ip, now) {
+            Some(last) => now.duration_since(last) >= throttle,
+            None => true,
+        }
+    }
+
+    fn disconnect(&self, ip: IpAddr) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        state.total -= 1;
+
+        if let Some(open) = state.connections.get_mut(&ip) {
+            *open -= 1;
+            if *open == 0 {
+                state.connections.remove(&ip);
+            }
+        }
+    }
+}
+
+/// An open connection counted against the limits.
+pub struct Connection {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+}
+
+impl Connection {
+    /// Records a login attempt from `ip`, returning false if the address has to wait before
+    /// logging in again. This is the address the player is known by, which a forwarding proxy
+    /// can change after the connection was counted.
+    pub fn allow_login(&self, ip: IpAddr) -> bool {
+        self.limiter.allow_login(ip)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.limiter.disconnect(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn limiter(config: Config) -> Arc<Limiter> {
+        Arc::new(Limiter::new(&config))
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn connections_per_address_are_capped() {
+        let limiter = limiter(Config {
+            max_connections_per_ip: Some(2),
+            ..Config::for_tests()
+        });
+
+        let _first = limiter.connect(ip(1)).unwrap();
+        let _second = limiter.connect(ip(1)).unwrap();
+        assert!(limiter.connect(ip(1)).is_none());
+        assert!(limiter.connect(ip(2)).is_some());
+    }
+
+    #[test]
+    fn connections_are_capped_in_total() {
+        let limiter = limiter(Config {
+            max_connections: Some(2),
+            ..Config::for_tests()
+        });
+
+        let _first = limiter.connect(ip(1)).unwrap();
+        let _second = limiter.connect(ip(2)).unwrap();
+        assert!(limiter.connect(ip(3)).is_none());
+    }
+
+    #[test]
+    fn connections_stop_counting_once_dropped() {
+        let limiter = limiter(Config {
+            max_connections_per_ip: Some(1),
+            max_connections: Some(1),
+            ..Config::for_tests()
+        });
+
+        drop(limiter.connect(ip(1)).unwrap());
+        assert_eq!(limiter.state.lock().unwrap().total, 0);
+        assert!(limiter.state.lock().unwrap().connections.is_empty());
+
+        let _again = limiter.connect(ip(1)).unwrap();
+        assert!(limiter.connect(ip(2)).is_none());
+    }
+
+    #[test]
+    fn logins_wait_out_the_throttle() {
+        let limiter = limiter(Config {
+            connection_throttle: Some(Duration::from_millis(100)),
+            ..Config::for_tests()
+        });
+        let connection = limiter.connect(ip(1)).unwrap();
+
+        assert!(connection.allow_login(ip(1)));
+        assert!(!connection.allow_login(ip(1)));
+        assert!(connection.allow_login(ip(2)));
+
+        // A refused attempt starts the wait over
+        thread::sleep(Duration::from_millis(60));
+        assert!(!connection.allow_login(ip(1)));
+        thread::sleep(Duration::from_millis(60));
+        assert!(!connection.allow_login(ip(1)));
+        thread::sleep(Duration::from_millis(110));
+        assert!(connection.allow_login(ip(1)));
+    }
+
+    #[test]
+    fn forwarded_connections_share_the_proxys_address() {
+        let limiter = limiter(Config {
+            max_connections_per_ip: Some(1),
+            forwarding: Forwarding::BungeeCord,
+            ..Config::for_tests()
+        });
+
+        let _first = limiter.connect(ip(1)).unwrap();
+        assert!(limiter.connect(ip(1)).is_some());
+    }
+}
@@ -0,0 +1,367 @@
+use super::codec::ProtocolError;
+use super::packets::play::{clientbound, serverbound};
+use super::packets::play_1_14;
+use super::packets::{ClientboundPacket, Field, ServerboundPacket, VarInt};
+use super::Packet;
+use bytes::{BytesMut, IntoBuf};
+
+// The server has no view distance of its own yet, so 1.14 clients are told vanilla's default
+const VIEW_DISTANCE: i32 = 10;
+
+/// A release clients can join with. Packets are declared as they are in 1.13.2 and converted
+/// to and from the client's version in Play, the only state that differs between these.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    V1_13,
+    V1_13_1,
+    V1_13_2,
+    V1_14,
+    V1_14_1,
+    V1_14_2,
+    V1_14_3,
+    V1_14_4,
+}
+
+const VERSIONS: &[Version] = &[
+    Version::V1_13,
+    Version::V1_13_1,
+    Version::V1_13_2,
+    Version::V1_14,
+    Version::V1_14_1,
+    Version::V1_14_2,
+    Version::V1_14_3,
+    Version::V1_14_4,
+];
+
+/// Serverbound Play ids that moved in 1.14, as (1.13.2 id, 1.14 id). Packets with changed
+/// fields are converted separately.
+const SERVERBOUND_1_14: &[(i32, i32)] = &[
+    (serverbound::TeleportConfirm::ID, 0x00),
+    (serverbound::Chat::ID, 0x03),
+    (serverbound::ClientStatus::ID, 0x04),
+    (serverbound::ClientSettings::ID, 0x05),
+    (serverbound::TabComplete::ID, 0x06),
+    (serverbound::ConfirmTransaction::ID, 0x07),
+    (serverbound::EnchantItem::ID, 0x08),
+    (serverbound::CloseWindow::ID, 0x0A),
+    (serverbound::PluginMessage::ID, 0x0B),
+    (serverbound::QueryEntityNbt::ID, 0x0D),
+    (serverbound::KeepAlive::ID, 0x0F),
+    (serverbound::Player::ID, 0x14),
+    (serverbound::Position::ID, 0x11),
+    (serverbound::PosLook::ID, 0x12),
+    (serverbound::Look::ID, 0x13),
+    (serverbound::VehicleMove::ID, 0x15),
+    (serverbound::SteerBoat::ID, 0x16),
+    (serverbound::PickItem::ID, 0x17),
+    (serverbound::CraftRecipeRequest::ID, 0x18),
+    (serverbound::Abilities::ID, 0x19),
+    (serverbound::EntityAction::ID, 0x1B),
+    (serverbound::SteerVehicle::ID, 0x1C),
+    (serverbound::NameItem::ID, 0x1E),
+    (serverbound::ResourcePackStatus::ID, 0x1F),
+    (serverbound::SelectTrade::ID, 0x21),
+    (serverbound::SetBeaconEffect::ID, 0x22),
+    (serverbound::HeldItemChange::ID, 0x23),
+    (serverbound::Animation::ID, 0x2A),
+    (serverbound::Spectate::ID, 0x2B),
+    (serverbound::UseItem::ID, 0x2D),
+];
+
+/// Clientbound Play ids that moved in 1.14, as (1.13.2 id, 1.14 id).
+const CLIENTBOUND_1_14: &[(i32, i32)] = &[
+    (clientbound::PluginMessage::ID, 0x18),
+    (clientbound::Disconnect::ID, 0x1A),
+    (clientbound::KeepAlive::ID, 0x20),
+    (clientbound::Abilities::ID, 0x31),
+    (clientbound::PosLook::ID, 0x35),
+];
+
+impl Version {
+    pub const OLDEST: Version = Version::V1_13;
+    pub const LATEST: Version = Version::V1_14_4;
+
+    pub fn from_protocol(protocol: i32) -> Option<Version> {
+        VERSIONS
+            .iter()
+            .find(|version| version.protocol() == protocol)
+            .cloned()
+    }
+
+    pub fn protocol(self) -> i32 {
+        match self {
+            Version::V1_13 => 393,
+            Version::V1_13_1 => 401,
+            Version::V1_13_2 => 404,
+            Version::V1_14 => 477,
+            Version::V1_14_1 => 480,
+            Version::V1_14_2 => 485,
+            Version::V1_14_3 => 490,
+            Version::V1_14_4 => 498,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Version::V1_13 => "1.13",
+            Version::V1_13_1 => "1.13.1",
+            Version::V1_13_2 => "1.13.2",
+            Version::V1_14 => "1.14",
+            Version::V1_14_1 => "1.14.1",
+            Version::V1_14_2 => "1.14.2",
+            Version::V1_14_3 => "1.14.3",
+            Version::V1_14_4 => "1.14.4",
+        }
+    }
+
+    /// Converts a serverbound Play packet from the client's version, or returns `None` if it
+    /// isn't one the server declares.
+    pub fn read_play(self, mut packet: Packet) -> Result<Option<Packet>, ProtocolError> {
+        if self < Version::V1_14 {
+            return Ok(Some(packet));
+        }
+
+        use play_1_14::serverbound as v1_14;
+        let packet = match packet.id {
+            v1_14::QueryBlockNbt::ID => {
+                let p: v1_14::QueryBlockNbt = packet.read()?;
+                encode(
+                    serverbound::QueryBlockNbt::ID,
+                    &serverbound::QueryBlockNbt {
+                        transaction_id: p.transaction_id,
+                        location: p.location.0,
+                    },
+                )
+            }
+            v1_14::Digging::ID => {
+                let p: v1_14::Digging = packet.read()?;
+                encode(
+                    serverbound::Digging::ID,
+                    &serverbound::Digging {
+                        status: p.status,
+                        location: p.location.0,
+                        face: p.face,
+                    },
+                )
+            }
+            v1_14::UpdateSign::ID => {
+                let p: v1_14::UpdateSign = packet.read()?;
+                encode(
+                    serverbound::UpdateSign::ID,
+                    &serverbound::UpdateSign {
+                        location: p.location.0,
+                        line_1: p.line_1,
+                        line_2: p.line_2,
+                        line_3: p.line_3,
+                        line_4: p.line_4,
+                    },
+                )
+            }
+            // Whether the cursor was inside the block has no 1.13 equivalent and is dropped
+            v1_14::BlockPlacement::ID => {
+                let p: v1_14::BlockPlacement = packet.read()?;
+                encode(
+                    serverbound::BlockPlacement::ID,
+                    &serverbound::BlockPlacement {
+                        location: p.location.0,
+                        face: p.face,
+                        hand: p.hand,
+                        cursor_x: p.cursor_x,
+                        cursor_y: p.cursor_y,
+                        cursor_z: p.cursor_z,
+                    },
+                )
+            }
+            id => match SERVERBOUND_1_14.iter().find(|(_, new)| *new == id) {
+                Some((old, _)) => Packet::new(*old, packet.data),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(packet))
+    }
+
+    /// Converts a clientbound Play packet to the client's version, or returns `None` if the
+    /// client's version doesn't have it.
+    pub fn write_play(self, packet: Packet) -> Option<Packet> {
+        if self < Version::V1_14 {
+            return Some(packet);
+        }
+
+        use play_1_14::clientbound as v1_14;
+        let packet = match packet.id {
+            clientbound::ServerDifficulty::ID => {
+                let p: clientbound::ServerDifficulty = reread(packet);
+                encode(
+                    v1_14::ServerDifficulty::ID,
+                    &v1_14::ServerDifficulty {
+                        difficulty: p.difficulty,
+                        locked: false,
+                    },
+                )
+            }
+            clientbound::JoinGame::ID => {
+                let p: clientbound::JoinGame = reread(packet);
+                encode(
+                    v1_14::JoinGame::ID,
+                    &v1_14::JoinGame {
+                        entity_id: p.entity_id,
+                        gamemode: p.gamemode,
+                        dimension: p.dimension,
+                        max_players: p.max_players,
+                        level_type: p.level_type,
+                        view_distance: VarInt(VIEW_DISTANCE),
+                        reduced_debug_info: p.reduced_debug_info,
+                    },
+                )
+            }
+            clientbound::SpawnPosition::ID => {
+                let p: clientbound::SpawnPosition = reread(packet);
+                encode(
+                    v1_14::SpawnPosition::ID,
+                    &v1_14::SpawnPosition {
+                        location: play_1_14::Position(p.location),
+                    },
+                )
+            }
+            id => match CLIENTBOUND_1_14.iter().find(|(old, _)| *old == id) {
+                Some((_, new)) => Packet::new(*new, packet.data),
+                None => return None,
+            },
+        };
+
+        Some(packet)
+    }
+
+    /// The id a serverbound Play packet has in this version, or `None` if its fields changed
+    /// and it has to be sent with its own declaration.
+    pub fn serverbound_play_id(self, id: i32) -> Option<i32> {
+        if self < Version::V1_14 {
+            return Some(id);
+        }
+
+        SERVERBOUND_1_14
+            .iter()
+            .find(|(old, _)| *old == id)
+            .map(|(_, new)| *new)
+    }
+
+    /// The 1.13.2 id of a Play packet sent in this version, for reading it back out of a
+    /// capture. Packets with changed fields have to be read with their 1.14 declarations.
+    pub fn play_id(self, clientbound: bool, id: i32) -> Option<i32> {
+        if self < Version::V1_14 {
+            return Some(id);
+        }
+
+        let ids = if clientbound {
+            CLIENTBOUND_1_14
+        } else {
+            SERVERBOUND_1_14
+        };
+
+        ids.iter().find(|(_, new)| *new == id).map(|(old, _)| *old)
+    }
+}
+
+fn encode<P: Field>(id: i32, packet: &P) -> Packet {
+    let mut data = BytesMut::with_capacity(packet.size());
+    packet.encode(&mut data);
+    Packet::new(id, data.freeze())
+}
+
+// Reads back a packet the server encoded itself, so it can't be malformed
+fn reread<P: Field>(packet: Packet) -> P {
+    P::decode(&mut packet.data.into_buf()).expect("Unable to read back an encoded packet")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    // Serverbound ids as (1.13.2, 1.14) for packets that moved
+    const MOVED_SERVERBOUND: &[(i32, i32)] = &[
+        (0x02, 0x03), // Chat
+        (0x0E, 0x0F), // Keep Alive
+        (0x0F, 0x14), // Player
+        (0x10, 0x11), // Player Position
+        (0x11, 0x12), // Player Position And Look
+        (0x2A, 0x2D), // Use Item
+    ];
+
+    // Clientbound ids as (1.13.2, 1.14) for packets that moved
+    const MOVED_CLIENTBOUND: &[(i32, i32)] = &[
+        (0x19, 0x18), // Plugin Message
+        (0x1B, 0x1A), // Disconnect
+        (0x21, 0x20), // Keep Alive
+        (0x32, 0x35), // Player Position And Look
+    ];
+
+    fn packet(id: i32) -> Packet {
+        Packet::new(id, Bytes::from(&b"\x01\x02"[..]))
+    }
+
+    #[test]
+    fn serverbound_ids_round_trip() {
+        for &(old, new) in MOVED_SERVERBOUND {
+            assert_eq!(Version::V1_14.serverbound_play_id(old), Some(new));
+            assert_eq!(Version::V1_14_4.play_id(false, new), Some(old));
+
+            let read = Version::V1_14_4.read_play(packet(new)).unwrap().unwrap();
+            assert_eq!(read.id(), old);
+            assert_eq!(read.data(), &packet(new).data);
+        }
+    }
+
+    #[test]
+    fn clientbound_ids_round_trip() {
+        for &(old, new) in MOVED_CLIENTBOUND {
+            let written = Version::V1_14.write_play(packet(old)).unwrap();
+            assert_eq!(written.id(), new);
+            assert_eq!(written.data(), &packet(old).data);
+            assert_eq!(Version::V1_14_4.play_id(true, new), Some(old));
+        }
+    }
+
+    #[test]
+    fn ids_before_1_14_are_unchanged() {
+        for &(old, _) in MOVED_SERVERBOUND.iter().chain(MOVED_CLIENTBOUND) {
+            assert_eq!(Version::V1_13_2.serverbound_play_id(old), Some(old));
+            assert_eq!(Version::V1_13.play_id(true, old), Some(old));
+            assert_eq!(Version::V1_13_1.write_play(packet(old)).unwrap().id(), old);
+            assert_eq!(
+                Version::V1_13_2.read_play(packet(old)).unwrap().unwrap().id(),
+                old
+            );
+        }
+    }
+
+    #[test]
+    fn join_game_gains_a_view_distance_in_1_14() {
+        let join_game = clientbound::JoinGame {
+            entity_id: 1,
+            gamemode: 1,
+            dimension: 0,
+            difficulty: 2,
+            max_players: 0,
+            level_type: String::from("default"),
+            reduced_debug_info: false,
+        };
+
+        let written = Version::V1_14.write_play(Packet::encode(&join_game)).unwrap();
+        assert_eq!(written.id(), play_1_14::clientbound::JoinGame::ID);
+        let p: play_1_14::clientbound::JoinGame = reread(written);
+        assert_eq!(p.level_type, "default");
+        assert_eq!(p.view_distance.0, VIEW_DISTANCE);
+    }
+
+    #[test]
+    fn packets_without_a_1_13_equivalent_have_no_id() {
+        // Lock Difficulty, which is new in 1.14
+        assert!(Version::V1_14.read_play(packet(0x10)).unwrap().is_none());
+        assert_eq!(
+            Version::V1_14.serverbound_play_id(serverbound::Digging::ID),
+            None
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use crate::entities::player;
+use std::fmt;
 use std::sync::Weak;
 
 pub mod chunks;
 pub mod palette;
 
 pub struct World {
+    pub name: String,
     pub gamemode: player::Gamemode,
     pub hardcore: bool,
     pub dimension: Dimension,
@@ -12,6 +14,7 @@ pub struct World {
     pub level_type: LevelType,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector {
     pub x: f64,
     pub y: f64,
@@ -23,6 +26,8 @@ pub struct Location {
     pub x: f64,
     pub y: f64,
     pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
     pub world: Weak<World>,
 }
 
@@ -57,17 +62,18 @@ pub enum LevelType {
     Flat,
     LargeBiomes,
     Amplified,
+    #[allow(non_camel_case_types)]
     Default_1_1,
 }
 
-impl LevelType {
-    pub fn to_string(&self) -> &str {
-        match self {
+impl fmt::Display for LevelType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             LevelType::Default => "default",
             LevelType::Flat => "flat",
             LevelType::LargeBiomes => "largeBiomes",
             LevelType::Amplified => "amplified",
             LevelType::Default_1_1 => "default_1_1",
-        }
+        })
     }
 }
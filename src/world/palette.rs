@@ -1,5 +1,4 @@
 use serde::Deserialize;
-use serde_json::{Map, Value};
 use std::collections::HashMap;
 
 #[derive(Deserialize)]
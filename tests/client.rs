@@ -0,0 +1,322 @@
+mod common;
+
+use common::NobodyJoined;
+use obelisk::config::{Config, Forwarding};
+use obelisk::net;
+use obelisk::net::client::{Client, State};
+use obelisk::net::packets::play::{clientbound, serverbound};
+use obelisk::net::session::SessionVerifier;
+use obelisk::net::version::Version;
+use obelisk::world::Location;
+use obelisk::Obelisk;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// A server running on its own thread for the length of a test, stopped when dropped.
+struct TestServer {
+    address: SocketAddr,
+    server: Arc<RwLock<Obelisk>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// An offline mode server.
+    fn start() -> TestServer {
+        TestServer::start_with(config(false), Arc::new(NobodyJoined))
+    }
+
+    /// An online mode server, checking logins with `session_verifier`.
+    fn online(session_verifier: Arc<dyn SessionVerifier>) -> TestServer {
+        TestServer::start_with(config(true), session_verifier)
+    }
+
+    /// A server set up with `config`, apart from where it listens.
+    fn start_with(mut config: Config, session_verifier: Arc<dyn SessionVerifier>) -> TestServer {
+        // The OS picks a free port, which is given back for the server to take
+        let address = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        config.listen = vec![address];
+
+        let mut obelisk = Obelisk::new(config, session_verifier);
+        obelisk.spawn_location = Location {
+            x: 0.5,
+            y: 64.0,
+            z: -0.5,
+            yaw: 90.0,
+            ..obelisk.spawn_location
+        };
+        let server = Arc::new(RwLock::new(obelisk));
+
+        let thread = {
+            let server = server.clone();
+            thread::spawn(move || net::start(server).unwrap())
+        };
+
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "Server didn't start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        TestServer {
+            address,
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    fn connect(&self, version: Version) -> Client {
+        Client::connect(self.address, version).unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.read().unwrap().shutdown.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn config(online_mode: bool) -> Config {
+    Config {
+        online_mode,
+        ..common::config()
+    }
+}
+
+#[test]
+fn ping_reports_status() {
+    let server = TestServer::start();
+    let ping = server.connect(Version::V1_13_2).ping().unwrap();
+
+    assert_eq!(ping.status["version"]["protocol"], 404);
+    assert_eq!(ping.status["players"]["max"], 10);
+    assert_eq!(ping.status["players"]["online"], 0);
+    assert!(ping.status["description"]["text"].is_string());
+    assert!(ping.status.get("favicon").is_none());
+}
+
+#[test]
+fn ping_counts_players_as_they_join() {
+    let server = TestServer::start();
+    let mut client = server.connect(Version::LATEST);
+    let uuid = client.login("Alex").unwrap();
+    client.spawn().unwrap();
+
+    let ping = server.connect(Version::LATEST).ping().unwrap();
+    assert_eq!(ping.status["players"]["online"], 1);
+    assert_eq!(ping.status["players"]["sample"][0]["name"], "Alex");
+    assert_eq!(
+        ping.status["players"]["sample"][0]["id"],
+        uuid.to_hyphenated().to_string()
+    );
+}
+
+#[test]
+fn pings_are_answered_while_the_server_is_locked() {
+    let server = TestServer::start();
+    // Once it has answered one the server has everything it needs
+    server.connect(Version::LATEST).ping().unwrap();
+    let _locked = server.server.write().unwrap();
+
+    let ping = server.connect(Version::V1_13_2).ping().unwrap();
+    assert_eq!(ping.status["version"]["protocol"], 404);
+    assert_eq!(ping.status["players"]["online"], 0);
+}
+
+#[test]
+fn players_spawn_in_every_version() {
+    let server = TestServer::start();
+
+    for version in &[Version::OLDEST, Version::V1_13_2, Version::LATEST] {
+        let mut client = server.connect(*version);
+        let uuid = client.login("Alex").unwrap();
+        assert_eq!(client.state(), State::Play);
+        assert_eq!(uuid, Uuid::new_v5(&Uuid::NAMESPACE_DNS, b"Alex"));
+
+        let position = client.spawn().unwrap();
+        assert_eq!((position.x, position.y, position.z), (0.5, 64.0, -0.5));
+        assert_eq!(position.yaw, 90.0);
+        assert!(server.server.read().unwrap().players.contains_key(&uuid));
+    }
+}
+
+#[test]
+fn server_brand_is_sent_on_spawn() {
+    let server = TestServer::start();
+    let mut client = server.connect(Version::V1_13_2);
+    client.login("Steve").unwrap();
+    client.spawn().unwrap();
+
+    let brand = loop {
+        let packet = client.recv().unwrap();
+        if let Some(message) = client.read::<clientbound::PluginMessage>(&packet).unwrap() {
+            break message;
+        }
+    };
+    assert_eq!(brand.channel, "minecraft:brand");
+    assert_eq!(&brand.data.0[1..], b"Obelisk");
+}
+
+#[test]
+fn leaving_removes_the_player() {
+    let server = TestServer::start();
+    let mut client = server.connect(Version::LATEST);
+    let uuid = client.login("Alex").unwrap();
+    client.spawn().unwrap();
+    drop(client);
+
+    let left = Instant::now();
+    while server.server.read().unwrap().players.contains_key(&uuid) {
+        assert!(
+            left.elapsed() < Duration::from_secs(5),
+            "Player wasn't removed"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn stopping_disconnects_players() {
+    let server = TestServer::start();
+    let mut client = server.connect(Version::V1_13_2);
+    client.login("Alex").unwrap();
+    client.spawn().unwrap();
+
+    server.server.read().unwrap().shutdown.stop();
+    let error = loop {
+        if let Err(e) = client.recv() {
+            break e;
+        }
+    };
+
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(error.to_string(), "Server closed");
+}
+
+#[test]
+fn logging_in_twice_is_refused() {
+    let server = TestServer::start();
+    let mut first = server.connect(Version::LATEST);
+    let uuid = first.login("Alex").unwrap();
+    first.spawn().unwrap();
+
+    let error = server.connect(Version::LATEST).login("Alex").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(
+        error.to_string(),
+        "You are already logged in to this server"
+    );
+
+    // The refused connection closing mustn't take the first one's player with it
+    thread::sleep(Duration::from_millis(100));
+    assert!(server.server.read().unwrap().players.contains_key(&uuid));
+}
+
+#[test]
+fn moving_to_an_invalid_position_disconnects() {
+    let server = TestServer::start();
+    let mut client = server.connect(Version::V1_13_2);
+    client.login("Alex").unwrap();
+    let spawn = client.spawn().unwrap();
+
+    client
+        .send(&serverbound::Position {
+            x: f64::NAN,
+            y: spawn.y,
+            z: spawn.z,
+            on_ground: true,
+        })
+        .unwrap();
+
+    let error = loop {
+        if let Err(e) = client.recv() {
+            break e;
+        }
+    };
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(error.to_string(), "Invalid move player packet received");
+}
+
+#[test]
+fn online_mode_logins_are_encrypted_and_verified() {
+    let (verifier, request) = common::stub_server(
+        "200 OK",
+        r#"{
+            "id": "069a79f444e94726a5befca90e38aaf5",
+            "name": "Notch",
+            "properties": [{"name": "textures", "value": "abc", "signature": "def"}]
+        }"#,
+    );
+    let server = TestServer::online(Arc::new(verifier));
+    let mut client = server.connect(Version::LATEST);
+
+    let uuid = client.login("Notch").unwrap();
+    assert_eq!(
+        uuid.to_simple().to_string(),
+        "069a79f444e94726a5befca90e38aaf5"
+    );
+    assert_eq!(
+        request.join().unwrap(),
+        format!(
+            "GET /session/minecraft/hasJoined?username=Notch&serverId={} HTTP/1.1\r\n",
+            client.server_hash().unwrap()
+        )
+    );
+
+    // Everything from here on is encrypted and compressed
+    client.spawn().unwrap();
+    let server = server.server.read().unwrap();
+    let player = &server.players[&uuid];
+    assert_eq!(player.username, "Notch");
+    assert_eq!(player.properties[0].signature.as_deref(), Some("def"));
+}
+
+#[test]
+fn online_mode_logins_need_a_session() {
+    let (verifier, _) = common::stub_server("204 No Content", "");
+    let server = TestServer::online(Arc::new(verifier));
+
+    let error = server.connect(Version::LATEST).login("Notch").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(error.to_string(), "Failed to verify username!");
+    assert!(server.server.read().unwrap().players.is_empty());
+}
+
+#[test]
+fn forwarded_players_are_throttled_by_their_own_address() {
+    let config = Config {
+        forwarding: Forwarding::BungeeCord,
+        connection_throttle: Some(Duration::from_secs(60)),
+        ..config(false)
+    };
+    let server = TestServer::start_with(config, Arc::new(NobodyJoined));
+
+    // Both come through the same proxy, which is all the server sees them connect from
+    let join = |username: &str, address: &str| {
+        let uuid = Uuid::new_v5(&Uuid::NAMESPACE_DNS, username.as_bytes());
+        let mut client = server.connect(Version::LATEST);
+        client.set_server_address(format!("localhost\0{}\0{}", address, uuid.to_simple()));
+        client.login(username).map(|_| client)
+    };
+
+    let _alex = join("Alex", "198.51.100.1").unwrap();
+    let _steve = join("Steve", "198.51.100.2").unwrap();
+
+    let error = join("Sam", "198.51.100.1").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(
+        error.to_string(),
+        "Connection throttled! Please wait before reconnecting."
+    );
+}
@@ -0,0 +1,55 @@
+use obelisk::config::Config;
+use obelisk::net::session::{HttpSessionVerifier, SessionVerifier, Verification};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+/// The defaults, except for what reaches for files in the working directory and the login
+/// throttle, so tests can run side by side and log in as often as they like.
+pub fn config() -> Config {
+    Config {
+        connection_throttle: None,
+        favicon: None,
+        player_data: None,
+        ..Config::default()
+    }
+}
+
+/// A session server nobody has joined through, for tests that never get as far as asking it.
+pub struct NobodyJoined;
+
+impl SessionVerifier for NobodyJoined {
+    fn has_joined(&self, _username: &str, _server_hash: &str) -> Verification {
+        Box::new(futures::future::ok(None))
+    }
+}
+
+/// A session server on its own thread that answers one request with `status` and `body`,
+/// handing back the request line it got.
+pub fn stub_server(status: &str, body: &str) -> (HttpSessionVerifier, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    let request = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+        let mut header = String::new();
+        while header != "\r\n" {
+            header.clear();
+            reader.read_line(&mut header).unwrap();
+        }
+
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        request
+    });
+
+    (HttpSessionVerifier::new(&url).unwrap(), request)
+}